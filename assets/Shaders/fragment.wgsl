@@ -2,6 +2,8 @@
 var my_array_texture: texture_2d_array<f32>;
 @group(1) @binding(1)
 var my_array_texture_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> sky_brightness: f32;
 
 @fragment
 fn fragment(
@@ -9,12 +11,21 @@ fn fragment(
 @location(1) world_normal: vec3<f32>,
 @location(2) uv: vec2<f32>,
 @location(3) layer: i32,
-@location(4) light_level: f32,
+@location(4) sky_light_level: f32,
 @location(5) ambient_occlusion: f32,
+@location(6) block_light_level: vec3<f32>,
 ) -> @location(0) vec4<f32> {
     var color = textureSample(my_array_texture, my_array_texture_sampler, uv, layer);
+#ifdef TRANSLUCENT
+    if (color.a < 0.01) {
+        discard;
+    }
+#else
     if (color.a < 0.5) {
         discard;
     }
-    return color * light_level * ((ambient_occlusion + 0.5) / 3.5);
+#endif
+    // the sky fades with the time of day, block light does not
+    let light_level = max(vec3<f32>(sky_light_level * sky_brightness), block_light_level);
+    return vec4<f32>(color.rgb * light_level * ((ambient_occlusion + 0.5) / 3.5), color.a);
 }
\ No newline at end of file
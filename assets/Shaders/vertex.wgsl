@@ -7,16 +7,18 @@ struct Vertex {
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) layer: i32,
-    @location(4) light_level: f32,
+    @location(4) sky_light_level: f32,
     @location(5) ambient_occlusion: f32,
+    @location(6) block_light_level: vec3<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     #import bevy_pbr::mesh_vertex_output
     @location(3) layer: i32,
-    @location(4) light_level: f32,
+    @location(4) sky_light_level: f32,
     @location(5) ambient_occlusion: f32,
+    @location(6) block_light_level: vec3<f32>,
 };
 
 @vertex
@@ -29,7 +31,8 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     out.clip_position = mesh_position_world_to_clip(out.world_position);
     out.layer = vertex.layer;
-    out.light_level = vertex.light_level;
+    out.sky_light_level = vertex.sky_light_level;
+    out.block_light_level = vertex.block_light_level;
     out.ambient_occlusion = vertex.ambient_occlusion;
     return out;
 }
\ No newline at end of file
@@ -0,0 +1,414 @@
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+
+use crate::{
+    chunk::Chunk,
+    conveyors::{hand_off_items, Conveyor, LANES},
+    energy::EnergyBuffer,
+    fluid_transport::{balance_fluids, pump_fluids, FluidTank, Pump, BUCKET, FLUID_PIPE_CAPACITY, TANK_CAPACITY},
+    inventory::{add_to_slot, insert_into_slots, slot_has_room, take_from_slot, ItemStack, Slot},
+    items::{block_registry, StateProperty},
+    pipes::{move_items, ItemPipe},
+    world::World,
+};
+
+pub const CHEST_SLOTS: usize = 27;
+pub const FURNACE_INPUT: usize = 0;
+pub const FURNACE_FUEL: usize = 1;
+pub const FURNACE_OUTPUT: usize = 2;
+pub const SMELTING_TICKS: u32 = 200; // world ticks to smelt one item
+pub const ELECTRIC_FURNACE_INPUT: usize = 0;
+pub const ELECTRIC_FURNACE_OUTPUT: usize = 1;
+pub const ELECTRIC_SMELTING_TICKS: u32 = 100;
+const GENERATOR_OUTPUT: u32 = 40; // energy made each tick while burning
+const ELECTRIC_FURNACE_USE: u32 = 20; // energy used each tick while smelting
+pub const FLUID_GENERATOR_CAPACITY: u32 = 4 * BUCKET;
+const FLUID_PER_BURN: u32 = BUCKET / 10; // millibuckets burnt at once, a bucket burns as long as the fluid_burn_time of its fluid
+
+// blocks.ron gives the kind of block entity a block carries, it is created when the block is set and removed with it
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockEntityKind {
+    Chest,
+    Furnace,
+    Conveyor,
+    Generator,
+    ElectricFurnace,
+    ItemPipe,
+    ExtractionPipe,
+    Tank,
+    FluidPipe,
+    Pump,
+    FluidGenerator,
+}
+
+#[derive(Clone, Debug)]
+pub enum BlockEntity {
+    Chest(Chest),
+    Furnace(Furnace),
+    Conveyor(Conveyor),
+    Generator(Generator),
+    ElectricFurnace(ElectricFurnace),
+    ItemPipe(ItemPipe), // both kinds of pipes
+    Tank(FluidTank),
+    FluidPipe(FluidTank),
+    Pump(Pump),
+    FluidGenerator(FluidGenerator),
+}
+
+#[derive(Clone, Debug)]
+pub struct Chest {
+    pub slots: Vec<Slot>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Furnace {
+    pub slots: [Slot; 3],    // input, fuel and output
+    pub burn_time_left: u32, // ticks before the current fuel is burnt
+    pub progress: u32,       // ticks spent smelting the current input
+}
+
+// burns fuel into the energy buffer of its block, see energy
+#[derive(Clone, Debug, Default)]
+pub struct Generator {
+    pub slots: [Slot; 1], // fuel
+    pub burn_time_left: u32,
+}
+
+// smelts like a furnace, with the energy of its block instead of fuel
+#[derive(Clone, Debug, Default)]
+pub struct ElectricFurnace {
+    pub slots: [Slot; 2], // input and output
+    pub progress: u32,
+}
+
+// burns the fluids that have a fluid_burn_time into the energy buffer of its block
+#[derive(Clone, Debug)]
+pub struct FluidGenerator {
+    pub tank: FluidTank,
+    pub burn_time_left: u32,
+}
+
+impl BlockEntity {
+    pub fn new(kind: BlockEntityKind) -> Self {
+        match kind {
+            BlockEntityKind::Chest => BlockEntity::Chest(Chest { slots: vec![None; CHEST_SLOTS] }),
+            BlockEntityKind::Furnace => BlockEntity::Furnace(Furnace::default()),
+            BlockEntityKind::Conveyor => BlockEntity::Conveyor(Conveyor::default()),
+            BlockEntityKind::Generator => BlockEntity::Generator(Generator::default()),
+            BlockEntityKind::ElectricFurnace => BlockEntity::ElectricFurnace(ElectricFurnace::default()),
+            BlockEntityKind::ItemPipe => BlockEntity::ItemPipe(ItemPipe::default()),
+            BlockEntityKind::ExtractionPipe => BlockEntity::ItemPipe(ItemPipe { extracts: true, ..ItemPipe::default() }),
+            BlockEntityKind::Tank => BlockEntity::Tank(FluidTank::new(TANK_CAPACITY)),
+            BlockEntityKind::FluidPipe => BlockEntity::FluidPipe(FluidTank::new(FLUID_PIPE_CAPACITY)),
+            BlockEntityKind::Pump => BlockEntity::Pump(Pump::default()),
+            BlockEntityKind::FluidGenerator => BlockEntity::FluidGenerator(FluidGenerator {
+                tank: FluidTank::new(FLUID_GENERATOR_CAPACITY),
+                burn_time_left: 0,
+            }),
+        }
+    }
+
+    // belts and pipes have no slots, their items are on the move, fluids are not in slots either
+    pub fn slots_mut(&mut self) -> &mut [Slot] {
+        match self {
+            BlockEntity::Chest(chest) => &mut chest.slots,
+            BlockEntity::Furnace(furnace) => &mut furnace.slots,
+            BlockEntity::Conveyor(_) => &mut [],
+            BlockEntity::Generator(generator) => &mut generator.slots,
+            BlockEntity::ElectricFurnace(electric_furnace) => &mut electric_furnace.slots,
+            BlockEntity::ItemPipe(_) | BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => &mut [],
+        }
+    }
+
+    // whether a stack can be put by hand in the slot at index
+    pub fn accepts(&self, index: usize, stack: &ItemStack) -> bool {
+        match self {
+            BlockEntity::Chest(_) => true,
+            BlockEntity::Furnace(_) => {
+                let item = block_registry().get(stack.id);
+                match index {
+                    FURNACE_INPUT => item.smelts_into.is_some(),
+                    FURNACE_FUEL => item.burn_time > 0,
+                    _ => false,
+                }
+            }
+            BlockEntity::Conveyor(_) => false,
+            BlockEntity::Generator(_) => block_registry().get(stack.id).burn_time > 0,
+            BlockEntity::ElectricFurnace(_) => index == ELECTRIC_FURNACE_INPUT && block_registry().get(stack.id).smelts_into.is_some(),
+            BlockEntity::ItemPipe(_) | BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => false,
+        }
+    }
+
+    // returns what did not fit
+    pub fn insert(&mut self, stack: ItemStack) -> Option<ItemStack> {
+        match self {
+            BlockEntity::Chest(chest) => insert_into_slots(&mut chest.slots, stack),
+            BlockEntity::Furnace(furnace) => {
+                let item = block_registry().get(stack.id);
+                if item.smelts_into.is_some() {
+                    add_to_slot(&mut furnace.slots[FURNACE_INPUT], stack)
+                } else if item.burn_time > 0 {
+                    add_to_slot(&mut furnace.slots[FURNACE_FUEL], stack)
+                } else {
+                    Some(stack)
+                }
+            }
+            BlockEntity::Conveyor(conveyor) => {
+                let mut rest = Some(stack);
+                for lane in 0..LANES {
+                    rest = conveyor.insert(lane, rest?);
+                }
+                rest
+            }
+            BlockEntity::Generator(generator) if block_registry().get(stack.id).burn_time > 0 => add_to_slot(&mut generator.slots[0], stack),
+            BlockEntity::ElectricFurnace(electric_furnace) if block_registry().get(stack.id).smelts_into.is_some() => add_to_slot(&mut electric_furnace.slots[ELECTRIC_FURNACE_INPUT], stack),
+            BlockEntity::Generator(_) | BlockEntity::ElectricFurnace(_) => Some(stack),
+            BlockEntity::ItemPipe(pipe) => pipe.insert(stack, None),
+            BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => Some(stack),
+        }
+    }
+
+    // whether insert would take at least one item of the stack, without a copy of the block entity
+    pub fn can_accept(&self, stack: &ItemStack) -> bool {
+        let item = block_registry().get(stack.id);
+        match self {
+            BlockEntity::Chest(chest) => chest.slots.iter().any(|slot| slot_has_room(slot, stack)),
+            BlockEntity::Furnace(furnace) if item.smelts_into.is_some() => slot_has_room(&furnace.slots[FURNACE_INPUT], stack),
+            BlockEntity::Furnace(furnace) if item.burn_time > 0 => slot_has_room(&furnace.slots[FURNACE_FUEL], stack),
+            BlockEntity::Conveyor(conveyor) => (0..LANES).any(|lane| conveyor.has_room(lane)),
+            BlockEntity::Generator(generator) if item.burn_time > 0 => slot_has_room(&generator.slots[0], stack),
+            BlockEntity::ElectricFurnace(electric_furnace) if item.smelts_into.is_some() => slot_has_room(&electric_furnace.slots[ELECTRIC_FURNACE_INPUT], stack),
+            BlockEntity::ItemPipe(pipe) => pipe.has_room(),
+            _ => false,
+        }
+    }
+
+    // takes up to count items of the first stack that can be taken out, only the output for machines, the front item for belts
+    pub fn extract(&mut self, count: u32) -> Option<ItemStack> {
+        match self {
+            BlockEntity::Chest(chest) => chest.slots.iter_mut().find(|slot| slot.is_some()).and_then(|slot| take_from_slot(slot, count)),
+            BlockEntity::Furnace(furnace) => take_from_slot(&mut furnace.slots[FURNACE_OUTPUT], count),
+            BlockEntity::Conveyor(conveyor) => conveyor.extract(count),
+            BlockEntity::Generator(_) => None,
+            BlockEntity::ElectricFurnace(electric_furnace) => take_from_slot(&mut electric_furnace.slots[ELECTRIC_FURNACE_OUTPUT], count),
+            BlockEntity::ItemPipe(_) | BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => None,
+        }
+    }
+
+    // what extract would take, without taking it
+    pub fn extractable(&self, count: u32) -> Option<ItemStack> {
+        let stack = match self {
+            BlockEntity::Chest(chest) => chest.slots.iter().flatten().next(),
+            BlockEntity::Furnace(furnace) => furnace.slots[FURNACE_OUTPUT].as_ref(),
+            BlockEntity::Conveyor(conveyor) => conveyor.lanes.iter().find_map(|lane| lane.first()).map(|belt_item| &belt_item.stack),
+            BlockEntity::ElectricFurnace(electric_furnace) => electric_furnace.slots[ELECTRIC_FURNACE_OUTPUT].as_ref(),
+            _ => None,
+        }?;
+        stack.clone().split(count)
+    }
+
+    // an empty container is dropped as a plain item that stacks
+    pub fn is_empty(&self) -> bool {
+        match self {
+            BlockEntity::Chest(chest) => chest.slots.iter().all(|slot| slot.is_none()),
+            BlockEntity::Furnace(furnace) => furnace.slots.iter().all(|slot| slot.is_none()) && furnace.burn_time_left == 0,
+            BlockEntity::Conveyor(conveyor) => conveyor.is_empty(),
+            BlockEntity::Generator(generator) => generator.slots[0].is_none() && generator.burn_time_left == 0,
+            BlockEntity::ElectricFurnace(electric_furnace) => electric_furnace.slots.iter().all(|slot| slot.is_none()),
+            BlockEntity::ItemPipe(pipe) => pipe.items.is_empty(),
+            BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => self.fluid_tank().map_or(true, |tank| tank.amount == 0),
+        }
+    }
+
+    // the fluid of blocks that hold one, see fluid_transport
+    pub fn fluid_tank(&self) -> Option<&FluidTank> {
+        match self {
+            BlockEntity::Tank(tank) | BlockEntity::FluidPipe(tank) => Some(tank),
+            BlockEntity::Pump(pump) => Some(&pump.tank),
+            BlockEntity::FluidGenerator(fluid_generator) => Some(&fluid_generator.tank),
+            _ => None,
+        }
+    }
+
+    pub fn fluid_tank_mut(&mut self) -> Option<&mut FluidTank> {
+        match self {
+            BlockEntity::Tank(tank) | BlockEntity::FluidPipe(tank) => Some(tank),
+            BlockEntity::Pump(pump) => Some(&mut pump.tank),
+            BlockEntity::FluidGenerator(fluid_generator) => Some(&mut fluid_generator.tank),
+            _ => None,
+        }
+    }
+
+    pub fn is_lit(&self) -> bool {
+        match self {
+            BlockEntity::Furnace(furnace) => furnace.burn_time_left > 0,
+            BlockEntity::Generator(generator) => generator.burn_time_left > 0,
+            BlockEntity::FluidGenerator(fluid_generator) => fluid_generator.burn_time_left > 0,
+            _ => false,
+        }
+    }
+
+    // returns true if something changed and the chunk must be saved, energy is the buffer of blocks that are part of a network
+    fn tick(&mut self, energy: Option<&mut EnergyBuffer>) -> bool {
+        match self {
+            BlockEntity::Chest(_) => false,
+            BlockEntity::Furnace(furnace) => furnace.tick(),
+            BlockEntity::Conveyor(conveyor) => conveyor.tick(),
+            BlockEntity::Generator(generator) => energy.map_or(false, |energy| generator.tick(energy)),
+            BlockEntity::ElectricFurnace(electric_furnace) => energy.map_or(false, |energy| electric_furnace.tick(energy)),
+            BlockEntity::ItemPipe(pipe) => pipe.tick(),
+            BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) => false,
+            BlockEntity::Pump(pump) => pump.tick(),
+            BlockEntity::FluidGenerator(fluid_generator) => energy.map_or(false, |energy| fluid_generator.tick(energy)),
+        }
+    }
+}
+
+// what the input smelts into, if the output has room for it
+fn smelting_result(input: &Slot, output: &Slot) -> Option<ItemStack> {
+    let result = ItemStack::new(block_registry().get(input.as_ref()?.id).smelts_into?, 1);
+    match output {
+        None => Some(result),
+        Some(output) if output.stacks_with(&result) && output.count < output.max_count() => Some(result),
+        _ => None,
+    }
+}
+
+impl Furnace {
+    // fuel is only burnt when there is something to smelt, a burning fuel burns until its end
+    fn tick(&mut self) -> bool {
+        let result = smelting_result(&self.slots[FURNACE_INPUT], &self.slots[FURNACE_OUTPUT]);
+        if self.burn_time_left == 0 {
+            let fuel_burn_time = self.slots[FURNACE_FUEL].as_ref().map_or(0, |fuel| block_registry().get(fuel.id).burn_time);
+            if result.is_none() || fuel_burn_time == 0 {
+                let changed = self.progress > 0;
+                self.progress = 0;
+                return changed;
+            }
+            take_from_slot(&mut self.slots[FURNACE_FUEL], 1);
+            self.burn_time_left = fuel_burn_time;
+        }
+        self.burn_time_left -= 1;
+        match result {
+            Some(result) => {
+                self.progress += 1;
+                if self.progress >= SMELTING_TICKS {
+                    self.progress = 0;
+                    take_from_slot(&mut self.slots[FURNACE_INPUT], 1);
+                    add_to_slot(&mut self.slots[FURNACE_OUTPUT], result);
+                }
+            }
+            None => self.progress = 0,
+        }
+        true
+    }
+}
+
+impl Generator {
+    // fuel is only burnt when the buffer has room for what it makes
+    fn tick(&mut self, energy: &mut EnergyBuffer) -> bool {
+        if self.burn_time_left == 0 {
+            let fuel_burn_time = self.slots[0].as_ref().map_or(0, |fuel| block_registry().get(fuel.id).burn_time);
+            if fuel_burn_time == 0 || energy.capacity - energy.stored < GENERATOR_OUTPUT {
+                return false;
+            }
+            take_from_slot(&mut self.slots[0], 1);
+            self.burn_time_left = fuel_burn_time;
+        }
+        self.burn_time_left -= 1;
+        energy.stored = (energy.stored + GENERATOR_OUTPUT).min(energy.capacity);
+        true
+    }
+}
+
+impl FluidGenerator {
+    // like Generator, with the fluid of its tank
+    fn tick(&mut self, energy: &mut EnergyBuffer) -> bool {
+        if self.burn_time_left == 0 {
+            let burn_time = self.tank.fluid.map_or(0, |fluid| block_registry().get(fluid).fluid_burn_time);
+            if burn_time == 0 || self.tank.amount < FLUID_PER_BURN || energy.capacity - energy.stored < GENERATOR_OUTPUT {
+                return false;
+            }
+            self.tank.drain(FLUID_PER_BURN);
+            self.burn_time_left = (burn_time * FLUID_PER_BURN / BUCKET).max(1);
+        }
+        self.burn_time_left -= 1;
+        energy.stored = (energy.stored + GENERATOR_OUTPUT).min(energy.capacity);
+        true
+    }
+}
+
+impl ElectricFurnace {
+    // smelting waits without losing its progress while there is not enough energy
+    fn tick(&mut self, energy: &mut EnergyBuffer) -> bool {
+        let result = match smelting_result(&self.slots[ELECTRIC_FURNACE_INPUT], &self.slots[ELECTRIC_FURNACE_OUTPUT]) {
+            Some(result) => result,
+            None => {
+                let changed = self.progress > 0;
+                self.progress = 0;
+                return changed;
+            }
+        };
+        if energy.stored < ELECTRIC_FURNACE_USE {
+            return false;
+        }
+        energy.stored -= ELECTRIC_FURNACE_USE;
+        self.progress += 1;
+        if self.progress >= ELECTRIC_SMELTING_TICKS {
+            self.progress = 0;
+            take_from_slot(&mut self.slots[ELECTRIC_FURNACE_INPUT], 1);
+            add_to_slot(&mut self.slots[ELECTRIC_FURNACE_OUTPUT], result);
+        }
+        true
+    }
+}
+
+// called by ticks::tick_world
+pub fn tick_block_entities(world: &World) {
+    let chunks: Vec<Arc<RwLock<Chunk>>> = world.chunks.read().unwrap().values().cloned().collect();
+    let mut lit_changes = Vec::new();
+    let mut hand_offs = Vec::new();
+    let mut pipe_moves = Vec::new();
+    let mut pumps = Vec::new();
+    let mut fluid_holders = Vec::new();
+    let mut energy_lock = world.energy.write().unwrap();
+    for chunk in chunks {
+        let chunk_lock = chunk.read().unwrap();
+        let mut block_entities_lock = chunk_lock.block_entities.write().unwrap();
+        let mut changed = false;
+        for (world_position, block_entity) in block_entities_lock.iter_mut() {
+            let was_lit = block_entity.is_lit();
+            changed |= block_entity.tick(energy_lock.buffer_mut(*world_position));
+            if block_entity.is_lit() != was_lit {
+                lit_changes.push((*world_position, block_entity.is_lit()));
+            }
+            match block_entity {
+                BlockEntity::Conveyor(conveyor) if conveyor.has_item_at_end() => hand_offs.push(*world_position),
+                BlockEntity::ItemPipe(pipe) if pipe.is_ready() => pipe_moves.push(*world_position),
+                BlockEntity::Pump(pump) if pump.is_ready() => pumps.push(*world_position),
+                _ => {}
+            }
+            if block_entity.fluid_tank().is_some() {
+                fluid_holders.push(*world_position);
+            }
+        }
+        if changed {
+            *chunk_lock.modified.write().unwrap() = true;
+        }
+    }
+
+    drop(energy_lock);
+
+    // the block state is set and items leave their belt once the block entities are released, setting a block can create or remove one
+    hand_off_items(world, hand_offs);
+    move_items(world, pipe_moves);
+    pump_fluids(world, pumps);
+    balance_fluids(world, fluid_holders);
+    for (world_position, lit) in lit_changes {
+        if let Some((id, state)) = world.get_block_state(world_position) {
+            let item = block_registry().get(id);
+            world.set_block_state(world_position, id, item.state.with(state, StateProperty::Lit, lit as u8));
+        }
+    }
+}
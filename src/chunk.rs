@@ -1,11 +1,16 @@
 use std::sync::{Arc, RwLock};
 
 use crate::{
+    block_entities::BlockEntity,
     chunk_filling::ChunkFilling,
+    chunk_storage::ChunkStorage,
+    fluids::{mesh_fluids, FLOW_FACES},
     game_material::GameMaterial,
-    items::{FACES, ITEMS},
-    lighting::{diffuse_light_from_pos, recalculate_diffuse_light, recalculate_natural_light, NaturalLightModification},
+    greedy_meshing_inits::TranslucentPassCube,
+    items::{block_registry, AIR, FACES},
+    lighting::{apply_light_modifications, recalculate_diffuse_light, recalculate_items_light, recalculate_natural_light, LightModification, MIN_LIGHT_LEVEL},
     positions::{to_world_position, ChunkPosition, WorldPosition},
+    region::SavedChunk,
     structures::Modification,
     world,
 };
@@ -15,7 +20,7 @@ use bevy::{
         mesh::{Indices, MeshVertexAttribute},
         render_resource::{PrimitiveTopology, VertexFormat},
     },
-    utils::HashSet,
+    utils::{HashMap, HashSet},
 };
 use block_mesh::{
     greedy_quads,
@@ -27,8 +32,10 @@ use linked_hash_map::LinkedHashMap;
 pub const CHUNK_SIZE: u32 = 34;
 pub const REAL_CHUNK_SIZE: u32 = CHUNK_SIZE - 2;
 pub const ATTRIBUTE_LAYER: MeshVertexAttribute = MeshVertexAttribute::new("Layer", 988540917, VertexFormat::Sint32);
-pub const ATTRIBUTE_LIGHT_LEVEL: MeshVertexAttribute = MeshVertexAttribute::new("Light_Level", 988164917, VertexFormat::Float32);
+pub const ATTRIBUTE_SKY_LIGHT_LEVEL: MeshVertexAttribute = MeshVertexAttribute::new("Sky_Light_Level", 988164917, VertexFormat::Float32);
+pub const ATTRIBUTE_BLOCK_LIGHT_LEVEL: MeshVertexAttribute = MeshVertexAttribute::new("Block_Light_Level", 988164919, VertexFormat::Float32x3);
 pub const ATTRIBUTE_AO: MeshVertexAttribute = MeshVertexAttribute::new("Ambient_Occlusion", 988112155, VertexFormat::Float32);
+const TRANSLUCENT_MESH_OFFSET: f32 = CHUNK_SIZE as f32 / 2.0;
 
 pub type ChunkShape = ConstShape3u32<CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE>;
 
@@ -36,27 +43,28 @@ pub type ChunkShape = ConstShape3u32<CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE>;
 pub struct Cube {
     pub id: u16,
     pub natural_light_level: u8,
-    pub items_light_level: u8,
+    pub items_light_level: [u8; 3],
+    pub state: u8, // meaning given by the StateSchema of the block, the level for fluids
 }
 
 #[derive(Component)]
 pub struct Chunk {
-    pub cubes: Arc<RwLock<[Cube; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize]>>,
+    pub cubes: Arc<RwLock<ChunkStorage>>,
+    pub block_entities: RwLock<HashMap<WorldPosition, BlockEntity>>, // only for the cubes the chunk owns, not its padding
     pub position: ChunkPosition,
-    indices: Vec<u32>,
-    vertices: Vec<[f32; 3]>,
-    normals: Vec<[f32; 3]>,
-    uvs: Vec<[f32; 2]>,
-    layers: Vec<i32>,
-    light_levels: Vec<f32>,
-    ambient_occlusion: Vec<f32>,
+    opaque_mesh_data: MeshData,
+    translucent_mesh_data: MeshData,
     pub modifications: RwLock<LinkedHashMap<usize, Modification>>,
-    pub natural_light_modifications: RwLock<Vec<NaturalLightModification>>,
+    pub light_modifications: RwLock<Vec<LightModification>>,
     pub other_chunks_modifications: RwLock<LinkedHashMap<(usize, ChunkPosition), (ChunkPosition, Modification)>>,
-    pub other_chunks_natural_light_modifications: RwLock<Vec<(NaturalLightModification, ChunkPosition)>>,
+    pub other_chunks_light_modifications: RwLock<Vec<(LightModification, ChunkPosition)>>,
     pub filled: Arc<RwLock<bool>>,
+    pub modified: RwLock<bool>, // the chunk differs from what the generation would give and must be saved
     pub drawn: bool,
     gameobject: Option<Entity>,
+    mesh: Option<Handle<Mesh>>,
+    translucent_gameobject: Option<Entity>,
+    translucent_mesh: Option<Handle<Mesh>>,
     pub world: Arc<RwLock<world::World>>,
     pub update_count: u32,
     pub chunk_filling: ChunkFilling,
@@ -64,40 +72,28 @@ pub struct Chunk {
 
 impl Chunk {
     pub fn new(position: ChunkPosition, world: Arc<RwLock<world::World>>) -> Self {
-        let cubes = Arc::new(RwLock::new(
-            [Cube {
-                id: 0,
-                natural_light_level: 0,
-                items_light_level: 0,
-            }; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize],
-        ));
-        let indices = Vec::new();
-        let vertices = Vec::new();
-        let normals = Vec::new();
-        let uvs = Vec::new();
-        let layers = Vec::new();
-        let light_levels = Vec::new();
-        let modifications = RwLock::new(LinkedHashMap::new());
+        let cubes = Arc::new(RwLock::new(ChunkStorage::new()));
+        let modifications = RwLock::new(world.read().unwrap().unloaded_modifications.write().unwrap().remove(&position).unwrap_or_else(LinkedHashMap::new));
         let other_chunks_modifications = RwLock::new(LinkedHashMap::new());
         let gameobject = None;
 
         Self {
             cubes,
+            block_entities: RwLock::new(HashMap::new()),
             position,
-            indices,
-            vertices,
-            normals,
-            uvs,
-            layers,
-            light_levels,
-            ambient_occlusion: Vec::new(),
+            opaque_mesh_data: MeshData::default(),
+            translucent_mesh_data: MeshData::default(),
             modifications,
-            natural_light_modifications: RwLock::new(Vec::new()),
+            light_modifications: RwLock::new(Vec::new()),
             other_chunks_modifications,
-            other_chunks_natural_light_modifications: RwLock::new(Vec::new()),
+            other_chunks_light_modifications: RwLock::new(Vec::new()),
             filled: Arc::new(RwLock::new(false)),
+            modified: RwLock::new(false),
             drawn: false,
             gameobject,
+            mesh: None,
+            translucent_gameobject: None,
+            translucent_mesh: None,
             world,
             update_count: 0,
             chunk_filling: ChunkFilling::new(),
@@ -106,6 +102,37 @@ impl Chunk {
 
     pub fn fill_chunk(&self) {
         let mut chunk_heights = [0; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+        let saved_chunk = self.world.read().unwrap().region_storage.load_chunk(self.position);
+        if let Some(saved_chunk) = &saved_chunk {
+            let mut mod_lock = self.modifications.write().unwrap();
+            for modification in saved_chunk.modifications.iter() {
+                if !mod_lock.contains_key(&modification.position) {
+                    mod_lock.insert(modification.position, *modification);
+                }
+            }
+        }
+        match saved_chunk.as_ref().and_then(|saved_chunk| saved_chunk.cubes.as_ref()) {
+            Some(cubes) => {
+                *self.cubes.write().unwrap() = cubes.clone();
+                *self.block_entities.write().unwrap() = saved_chunk.as_ref().unwrap().block_entities.iter().cloned().collect();
+                chunk_heights = [CHUNK_SIZE - 1; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+            }
+            None => self.generate(&mut chunk_heights),
+        }
+        self.apply_self_modifications(&mut chunk_heights);
+        if self.modifications.read().unwrap().len() == 0 {
+            self.apply_chunk_heights(&mut chunk_heights);
+        }
+        self.sync_energy();
+        self.schedule_fluids();
+        *self.filled.write().unwrap() = true;
+        recalculate_natural_light(self);
+        recalculate_diffuse_light(self);
+        recalculate_items_light(self);
+    }
+
+    fn generate(&self, chunk_heights: &mut [u32; (CHUNK_SIZE * CHUNK_SIZE) as usize]) {
+        self.cubes.write().unwrap().fill_natural_light(MIN_LIGHT_LEVEL);
         for i in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
             let [x, y, z] = ChunkShape::delinearize(i);
             let world_position = WorldPosition {
@@ -117,15 +144,83 @@ impl Chunk {
             if y > chunk_heights[(x + z * CHUNK_SIZE) as usize] {
                 chunk_heights[(x + z * CHUNK_SIZE) as usize] = y;
             }
-            self.cubes.write().unwrap()[i as usize] = cube;
+            self.cubes.write().unwrap().set(i as usize, cube);
         }
-        self.apply_self_modifications(&mut chunk_heights);
-        if self.modifications.read().unwrap().len() == 0 {
-            self.apply_chunk_heights(&mut chunk_heights);
+        self.cubes.write().unwrap().compact();
+    }
+
+    // the cubes are read before the energy lock is taken, so filling chunks does not hold back the other fills and the world tick
+    fn sync_energy(&self) {
+        let block_registry = block_registry();
+        let mut blocks = Vec::new();
+        let cubes_lock = self.cubes.read().unwrap();
+        if cubes_lock.palette().iter().any(|id| block_registry.get(*id).energy.is_some()) {
+            for x in 1..=REAL_CHUNK_SIZE {
+                for y in 1..=REAL_CHUNK_SIZE {
+                    for z in 1..=REAL_CHUNK_SIZE {
+                        let id = cubes_lock.get_id(ChunkShape::linearize([x, y, z]) as usize);
+                        if block_registry.get(id).energy.is_some() {
+                            let world_position = WorldPosition {
+                                x: to_world_position(x, self.position.x),
+                                y: to_world_position(y, self.position.y),
+                                z: to_world_position(z, self.position.z),
+                            };
+                            blocks.push((world_position, id));
+                        }
+                    }
+                }
+            }
+        }
+        drop(cubes_lock);
+        let [min, max] = [1, REAL_CHUNK_SIZE].map(|i| WorldPosition {
+            x: to_world_position(i, self.position.x),
+            y: to_world_position(i, self.position.y),
+            z: to_world_position(i, self.position.z),
+        });
+        self.world.read().unwrap().energy.write().unwrap().sync(min, max, blocks);
+    }
+
+    // generated seas and lakes lie still until something changes next to them, only fluids with air to flow into are scheduled, scheduled ticks are not saved either
+    fn schedule_fluids(&self) {
+        let block_registry = block_registry();
+        let mut to_schedule = Vec::new();
+        let cubes_lock = self.cubes.read().unwrap();
+        for x in 1..=REAL_CHUNK_SIZE {
+            for y in 1..=REAL_CHUNK_SIZE {
+                for z in 1..=REAL_CHUNK_SIZE {
+                    let fluid = match block_registry.get(cubes_lock.get_id(ChunkShape::linearize([x, y, z]) as usize)).fluid {
+                        Some(fluid) => fluid,
+                        None => continue,
+                    };
+                    let next_to_air = FLOW_FACES.iter().any(|face| {
+                        let [dx, dy, dz] = FACES[*face];
+                        cubes_lock.get_id(ChunkShape::linearize([(x as i32 + dx as i32) as u32, (y as i32 + dy as i32) as u32, (z as i32 + dz as i32) as u32]) as usize) == AIR
+                    });
+                    if next_to_air {
+                        let world_position = WorldPosition {
+                            x: to_world_position(x, self.position.x),
+                            y: to_world_position(y, self.position.y),
+                            z: to_world_position(z, self.position.z),
+                        };
+                        to_schedule.push((world_position, fluid.tick_delay));
+                    }
+                }
+            }
+        }
+        drop(cubes_lock);
+        let world_read_lock = self.world.read().unwrap();
+        let mut tick_scheduler_lock = world_read_lock.tick_scheduler.write().unwrap();
+        for (world_position, tick_delay) in to_schedule {
+            tick_scheduler_lock.schedule(world_position, tick_delay);
+        }
+    }
+
+    pub fn to_saved_chunk(&self) -> SavedChunk {
+        SavedChunk {
+            cubes: if *self.filled.read().unwrap() { Some(self.cubes.read().unwrap().clone()) } else { None },
+            modifications: self.modifications.read().unwrap().values().copied().collect(),
+            block_entities: self.block_entities.read().unwrap().iter().map(|(world_position, block_entity)| (*world_position, block_entity.clone())).collect(),
         }
-        *self.filled.write().unwrap() = true;
-        recalculate_natural_light(self);
-        recalculate_diffuse_light(self);
     }
 
     fn apply_chunk_heights(&self, chunk_heights: &[u32; (CHUNK_SIZE * CHUNK_SIZE) as usize]) {
@@ -173,9 +268,11 @@ impl Chunk {
             for _ in 0..len {
                 let (_, modification) = mod_lock.pop_back().unwrap();
                 let position = modification.position;
-                if modification.force || cubes_lock[position].id == 0 {
+                if modification.force || cubes_lock.get_id(position) == 0 {
                     self.modify_neighbours(self.position, &modification);
-                    cubes_lock[position].id = modification.id;
+                    cubes_lock.set_id(position, modification.id);
+                    cubes_lock.set_state(position, modification.state);
+                    *self.modified.write().unwrap() = true;
                     let [x, y, z] = ChunkShape::delinearize(position as u32);
                     if y > chunk_heights[(x + z * CHUNK_SIZE) as usize] {
                         chunk_heights[(x + z * CHUNK_SIZE) as usize] = y;
@@ -186,18 +283,10 @@ impl Chunk {
         }
     }
 
-    pub fn apply_self_natural_light_modifications(&self) {
-        let len = self.natural_light_modifications.read().unwrap().len();
-        if len > 0 {
-            let mut mod_lock = self.natural_light_modifications.write().unwrap();
-            let mut cubes_lock = self.cubes.write().unwrap();
-            let mut other_chunks_natural_light_lock = self.other_chunks_natural_light_modifications.write().unwrap();
-            for _ in 0..len {
-                let modification = mod_lock.pop().unwrap();
-                let position = modification.position;
-                let [x, y, z] = ChunkShape::delinearize(position as u32);
-                diffuse_light_from_pos(self, &mut cubes_lock, x, y, z, modification.light_level, true, &mut other_chunks_natural_light_lock)
-            }
+    pub fn apply_self_light_modifications(&self) {
+        let modifications: Vec<LightModification> = self.light_modifications.write().unwrap().drain(..).collect();
+        if modifications.len() > 0 {
+            apply_light_modifications(self, modifications);
         }
     }
 
@@ -218,8 +307,8 @@ impl Chunk {
             modified_chunks.insert(chunk_position);
         }
 
-        while self.other_chunks_natural_light_modifications.read().unwrap().len() > 0 {
-            let (modification, chunk_position) = self.other_chunks_natural_light_modifications.write().unwrap().pop().unwrap();
+        while self.other_chunks_light_modifications.read().unwrap().len() > 0 {
+            let (modification, chunk_position) = self.other_chunks_light_modifications.write().unwrap().pop().unwrap();
             {
                 let mut chunks_lock = chunks.write().unwrap();
                 if !chunks_lock.contains_key(&chunk_position) {
@@ -227,15 +316,19 @@ impl Chunk {
                 }
             }
             let chunk = chunks.read().unwrap().get(&chunk_position).unwrap().clone();
-            if chunk.read().unwrap().cubes.read().unwrap()[modification.position].natural_light_level < modification.light_level {
-                chunk.read().unwrap().natural_light_modifications.write().unwrap().push(modification);
+            let light_level = chunk.read().unwrap().cubes.read().unwrap().get_light(modification.channel, modification.position);
+            if (!modification.removal && light_level < modification.light_level) || (modification.removal && light_level > modification.light_level) {
+                chunk.read().unwrap().light_modifications.write().unwrap().push(modification);
                 modified_chunks.insert(chunk_position);
             }
         }
 
         let chunks_to_update = self.world.read().unwrap().chunks_to_update.clone();
         for chunk_position in modified_chunks {
-            let chunk = chunks.read().unwrap().get(&chunk_position).unwrap().clone();
+            let chunk = match chunks.read().unwrap().get(&chunk_position) {
+                Some(chunk) => chunk.clone(),
+                None => continue,
+            };
             if chunk.read().unwrap().drawn {
                 chunks_to_update.write().unwrap().insert_if_absent(chunk_position);
             }
@@ -254,6 +347,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([REAL_CHUNK_SIZE + 1, modification_pos[1], modification_pos[2]]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -269,6 +363,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([0, modification_pos[1], modification_pos[2]]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -285,6 +380,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([modification_pos[0], REAL_CHUNK_SIZE + 1, modification_pos[2]]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -300,6 +396,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([modification_pos[0], 0, modification_pos[2]]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -316,6 +413,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([modification_pos[0], modification_pos[1], REAL_CHUNK_SIZE + 1]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -331,6 +429,7 @@ impl Chunk {
             let modification = Modification {
                 position: ChunkShape::linearize([modification_pos[0], modification_pos[1], 0]) as usize,
                 id: modification.id,
+                state: modification.state,
                 force: modification.force,
             };
             self.other_chunks_modifications
@@ -360,76 +459,78 @@ impl Chunk {
         }
     }
 
-    pub fn draw_mesh(&mut self, commands: &mut Commands, meshes: &mut ResMut<Assets<Mesh>>, material: Handle<GameMaterial>) {
-        if self.vertices.len() > 0 {
-            let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            mesh.set_indices(Some(Indices::U32(std::mem::replace(&mut self.indices, Vec::new()))));
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, std::mem::replace(&mut self.vertices, Vec::new()));
-            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, std::mem::replace(&mut self.uvs, Vec::new()));
-            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, std::mem::replace(&mut self.normals, Vec::new()));
-            mesh.insert_attribute(ATTRIBUTE_LAYER, std::mem::replace(&mut self.layers, Vec::new()));
-            mesh.insert_attribute(ATTRIBUTE_LIGHT_LEVEL, std::mem::replace(&mut self.light_levels, Vec::new()));
-            mesh.insert_attribute(ATTRIBUTE_AO, std::mem::replace(&mut self.ambient_occlusion, Vec::new()));
-            if self.gameobject != None {
-                commands.entity(self.gameobject.unwrap()).despawn();
-            }
+    // the translucent mesh is centred on the chunk because transparent entities are sorted back to front by their translation
+    pub fn draw_mesh(&mut self, commands: &mut Commands, meshes: &mut Assets<Mesh>, material: Handle<GameMaterial>, translucent_material: Handle<GameMaterial>) {
+        self.unload_mesh(commands, meshes);
+        let origin = Vec3::new(
+            self.position.x as f32 * REAL_CHUNK_SIZE as f32,
+            self.position.y as f32 * REAL_CHUNK_SIZE as f32,
+            self.position.z as f32 * REAL_CHUNK_SIZE as f32,
+        );
+        if let Some(mesh) = std::mem::take(&mut self.opaque_mesh_data).into_mesh() {
+            let mesh = meshes.add(mesh);
+            self.mesh = Some(mesh.clone());
             let spawned = commands.spawn_bundle(MaterialMeshBundle {
-                mesh: meshes.add(mesh),
-                material: material.clone(),
-                transform: Transform::from_xyz(
-                    self.position.x as f32 * REAL_CHUNK_SIZE as f32,
-                    self.position.y as f32 * REAL_CHUNK_SIZE as f32,
-                    self.position.z as f32 * REAL_CHUNK_SIZE as f32,
-                ),
+                mesh,
+                material,
+                transform: Transform::from_translation(origin),
                 ..default()
             });
             self.gameobject = Some(spawned.id());
         }
+        if let Some(mesh) = std::mem::take(&mut self.translucent_mesh_data).into_mesh() {
+            let mesh = meshes.add(mesh);
+            self.translucent_mesh = Some(mesh.clone());
+            let spawned = commands.spawn_bundle(MaterialMeshBundle {
+                mesh,
+                material: translucent_material,
+                transform: Transform::from_translation(origin + Vec3::splat(TRANSLUCENT_MESH_OFFSET)),
+                ..default()
+            });
+            self.translucent_gameobject = Some(spawned.id());
+        }
+    }
+
+    pub fn unload_mesh(&mut self, commands: &mut Commands, meshes: &mut Assets<Mesh>) {
+        for gameobject in [self.gameobject.take(), self.translucent_gameobject.take()].into_iter().flatten() {
+            commands.entity(gameobject).despawn();
+        }
+        for mesh in [self.mesh.take(), self.translucent_mesh.take()].into_iter().flatten() {
+            meshes.remove(mesh);
+        }
     }
 
     fn greedy_meshing(&mut self) {
+        self.opaque_mesh_data = MeshData::default();
+        self.translucent_mesh_data = MeshData::default();
+        // a chunk made of a single block, padding included, has no visible face
+        if self.cubes.read().unwrap().is_uniform() {
+            return;
+        }
+        let has_translucent_cubes = self.cubes.read().unwrap().palette().iter().any(|id| block_registry().get(*id).is_translucent);
+        let has_fluids = self.cubes.read().unwrap().palette().iter().any(|id| block_registry().is_fluid(*id));
         let mut buffer = GreedyQuadsBuffer::new((REAL_CHUNK_SIZE * REAL_CHUNK_SIZE * REAL_CHUNK_SIZE) as usize);
-        greedy_quads(&*self.cubes.read().unwrap(), &ChunkShape {}, [0; 3], [CHUNK_SIZE as u32 - 1; 3], &RIGHT_HANDED_Y_UP_CONFIG.faces, &mut buffer);
-        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-        let mut i = 0;
-
-        self.vertices = Vec::with_capacity(buffer.quads.num_quads() * 4);
-        self.indices = Vec::with_capacity(buffer.quads.num_quads() * 6);
-        self.normals = Vec::with_capacity(buffer.quads.num_quads() * 4);
-        self.uvs = Vec::with_capacity(buffer.quads.num_quads() * 4);
-        self.layers = Vec::with_capacity(buffer.quads.num_quads() * 4);
-        self.light_levels = Vec::with_capacity(buffer.quads.num_quads() * 4);
-        self.ambient_occlusion = Vec::with_capacity(buffer.quads.num_quads() * 4);
-
-        for (group, face) in buffer.quads.groups.into_iter().zip(faces.into_iter()) {
-            for quad in group.into_iter() {
-                self.indices.extend_from_slice(&face.quad_mesh_indices(self.vertices.len() as u32));
-                self.vertices.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
-                self.normals.extend_from_slice(&face.quad_mesh_normals());
-                self.uvs.extend_from_slice(&face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, &quad));
-                let cube_id = ITEMS[self.cubes.read().unwrap()[ChunkShape::linearize([quad.minimum[0], quad.minimum[1], quad.minimum[2]]) as usize].id as usize].textures[i] as i32;
-                self.layers.extend_from_slice(&[cube_id, cube_id, cube_id, cube_id]);
-                let light_level = self.cubes.read().unwrap()[ChunkShape::linearize([
-                    (quad.minimum[0] as i8 + FACES[i][0]) as u32,
-                    (quad.minimum[1] as i8 + FACES[i][1]) as u32,
-                    (quad.minimum[2] as i8 + FACES[i][2]) as u32,
-                ]) as usize]
-                    .natural_light_level as f32
-                    / 255.0;
-                self.light_levels.extend_from_slice(&[light_level, light_level, light_level, light_level]);
-                let ao = *&face.quad_mesh_ao(&quad);
-                for ambient in ao {
-                    self.ambient_occlusion.push(ambient as f32);
-                }
-            }
-            i += 1;
+        let cubes = self.cubes.read().unwrap().to_cubes();
+
+        // translucent cubes are empty for this pass so the faces behind them are kept
+        greedy_quads(&cubes, &ChunkShape {}, [0; 3], [CHUNK_SIZE as u32 - 1; 3], &RIGHT_HANDED_Y_UP_CONFIG.faces, &mut buffer);
+        self.opaque_mesh_data = MeshData::from_quads(&buffer, &cubes, |_| true, 0.0);
+
+        if has_translucent_cubes {
+            let translucent_pass_cubes: Vec<TranslucentPassCube> = cubes.iter().map(|cube| TranslucentPassCube(*cube)).collect();
+            greedy_quads(&translucent_pass_cubes, &ChunkShape {}, [0; 3], [CHUNK_SIZE as u32 - 1; 3], &RIGHT_HANDED_Y_UP_CONFIG.faces, &mut buffer);
+            // the other cubes only hide translucent faces, their own faces are already in the opaque mesh
+            self.translucent_mesh_data = MeshData::from_quads(&buffer, &cubes, |cube| block_registry().get(cube.id).is_translucent, -TRANSLUCENT_MESH_OFFSET);
+        }
+        if has_fluids {
+            mesh_fluids(&cubes, &mut self.opaque_mesh_data, &mut self.translucent_mesh_data, -TRANSLUCENT_MESH_OFFSET);
         }
     }
 
     pub fn update_mesh(&mut self) {
         let mut chunk_heights = [0; (CHUNK_SIZE * CHUNK_SIZE) as usize];
         self.apply_self_modifications(&mut chunk_heights);
-        self.apply_self_natural_light_modifications();
+        self.apply_self_light_modifications();
 
         self.greedy_meshing();
         self.drawn = true;
@@ -440,3 +541,110 @@ impl Chunk {
         }
     }
 }
+
+#[derive(Default)]
+pub struct MeshData {
+    indices: Vec<u32>,
+    vertices: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    layers: Vec<i32>,
+    sky_light_levels: Vec<f32>,
+    block_light_levels: Vec<[f32; 3]>,
+    ambient_occlusion: Vec<f32>,
+}
+
+impl MeshData {
+    // corners of the faces of a unit cube in the order of FACES, counter clockwise seen from the front, see push_face
+    pub const UNIT_CUBE_FACES: [[[f32; 3]; 4]; 6] = [
+        [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+        [[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]],
+        [[1.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]],
+        [[0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+        [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]],
+    ];
+
+    // texture coordinates of the corners of a face, textures stay upright on the sides
+    pub fn face_uvs(face: usize, corners: [[f32; 3]; 4]) -> [[f32; 2]; 4] {
+        match face {
+            1 | 4 => corners.map(|[x, _, z]| [x, z]),
+            0 | 3 => corners.map(|[_, y, z]| [z, 1.0 - y]),
+            _ => corners.map(|[x, y, _]| [x, 1.0 - y]),
+        }
+    }
+
+    // keep filters the quads by the cube they belong to, offset moves every vertex on the three axes
+    fn from_quads(buffer: &GreedyQuadsBuffer, cubes: &[Cube], keep: impl Fn(&Cube) -> bool, offset: f32) -> Self {
+        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        let nb_quads = buffer.quads.num_quads();
+        let mut mesh_data = Self {
+            indices: Vec::with_capacity(nb_quads * 6),
+            vertices: Vec::with_capacity(nb_quads * 4),
+            normals: Vec::with_capacity(nb_quads * 4),
+            uvs: Vec::with_capacity(nb_quads * 4),
+            layers: Vec::with_capacity(nb_quads * 4),
+            sky_light_levels: Vec::with_capacity(nb_quads * 4),
+            block_light_levels: Vec::with_capacity(nb_quads * 4),
+            ambient_occlusion: Vec::with_capacity(nb_quads * 4),
+        };
+
+        for (i, (group, face)) in buffer.quads.groups.iter().zip(faces.iter()).enumerate() {
+            for quad in group.iter() {
+                let cube = cubes[ChunkShape::linearize(quad.minimum) as usize];
+                if !keep(&cube) {
+                    continue;
+                }
+                mesh_data.indices.extend_from_slice(&face.quad_mesh_indices(mesh_data.vertices.len() as u32));
+                mesh_data.vertices.extend(face.quad_mesh_positions(quad, 1.0).map(|[x, y, z]| [x + offset, y + offset, z + offset]));
+                mesh_data.normals.extend_from_slice(&face.quad_mesh_normals());
+                let (layer, transposed) = block_registry().get(cube.id).face_textures(cube.state)[i];
+                mesh_data.layers.extend_from_slice(&[layer as i32; 4]);
+                let uvs = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, quad);
+                mesh_data.uvs.extend(uvs.map(|[u, v]| if transposed { [v, u] } else { [u, v] }));
+                let light_cube = cubes[ChunkShape::linearize([
+                    (quad.minimum[0] as i8 + FACES[i][0]) as u32,
+                    (quad.minimum[1] as i8 + FACES[i][1]) as u32,
+                    (quad.minimum[2] as i8 + FACES[i][2]) as u32,
+                ]) as usize];
+                let sky_light_level = light_cube.natural_light_level as f32 / 255.0;
+                mesh_data.sky_light_levels.extend_from_slice(&[sky_light_level; 4]);
+                let block_light_level = light_cube.items_light_level.map(|items_light_level| items_light_level as f32 / 255.0);
+                mesh_data.block_light_levels.extend_from_slice(&[block_light_level; 4]);
+                for ambient in face.quad_mesh_ao(quad) {
+                    mesh_data.ambient_occlusion.push(ambient as f32);
+                }
+            }
+        }
+        mesh_data
+    }
+
+    // corners go counter clockwise seen from the front of the face
+    pub fn push_face(&mut self, positions: [[f32; 3]; 4], normal: [f32; 3], uvs: [[f32; 2]; 4], layer: i32, light_cube: Cube, ambient_occlusion: f32) {
+        let start = self.vertices.len() as u32;
+        self.indices.extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+        self.vertices.extend_from_slice(&positions);
+        self.normals.extend_from_slice(&[normal; 4]);
+        self.uvs.extend_from_slice(&uvs);
+        self.layers.extend_from_slice(&[layer; 4]);
+        self.sky_light_levels.extend_from_slice(&[light_cube.natural_light_level as f32 / 255.0; 4]);
+        self.block_light_levels.extend_from_slice(&[light_cube.items_light_level.map(|items_light_level| items_light_level as f32 / 255.0); 4]);
+        self.ambient_occlusion.extend_from_slice(&[ambient_occlusion; 4]);
+    }
+
+    pub fn into_mesh(self) -> Option<Mesh> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(ATTRIBUTE_LAYER, self.layers);
+        mesh.insert_attribute(ATTRIBUTE_SKY_LIGHT_LEVEL, self.sky_light_levels);
+        mesh.insert_attribute(ATTRIBUTE_BLOCK_LIGHT_LEVEL, self.block_light_levels);
+        mesh.insert_attribute(ATTRIBUTE_AO, self.ambient_occlusion);
+        Some(mesh)
+    }
+}
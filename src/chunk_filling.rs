@@ -2,20 +2,50 @@ use noise::{NoiseFn, Perlin};
 
 use crate::{
     chunk::{Chunk, Cube},
-    items::Items,
+    items::{block_registry, AIR},
     structures::generate_tree,
     lighting::MIN_LIGHT_LEVEL, positions::WorldPosition,
 };
 
 const SEED: u32 = 0;
+const LAVA_LEVEL: f64 = -110.0; // caves below are flooded with lava
+const SEA_LEVEL: f64 = 12.0; // surface air below is filled with water sources
+
+// ids of the blocks used by the generator, resolved once from the block registry
+pub struct GenerationIds {
+    pub stone: u16,
+    pub sand: u16,
+    pub lava: u16,
+    pub water: u16,
+    pub wood: u16,
+    pub leaves: u16,
+}
+
+impl GenerationIds {
+    fn new() -> Self {
+        let block_registry = block_registry();
+        Self {
+            stone: block_registry.expect_id("stone"),
+            sand: block_registry.expect_id("sand"),
+            lava: block_registry.expect_id("lava"),
+            water: block_registry.expect_id("water"),
+            wood: block_registry.expect_id("wood"),
+            leaves: block_registry.expect_id("leaves"),
+        }
+    }
+}
 
 pub struct ChunkFilling {
     pub noise: Perlin,
+    pub ids: GenerationIds,
 }
 
 impl ChunkFilling {
     pub fn new() -> Self {
-        Self { noise: Perlin::new(SEED) }
+        Self {
+            noise: Perlin::new(SEED),
+            ids: GenerationIds::new(),
+        }
     }
 
     fn fill_surface(&self, world_position: WorldPosition, current_chunk: &Chunk, generate_structure: bool) -> u16 {
@@ -23,15 +53,17 @@ impl ChunkFilling {
 
         let noise = self.noise.get([gx / 32.0, gz / 32.0]) * 16.0 + 16.0;
         if gy < noise {
-            return Items::Sand as u16;
+            return self.ids.sand;
+        } else if gy < SEA_LEVEL {
+            return self.ids.water;
         } else if gy < noise + 1.0 && generate_structure {
             if self.noise.get([gx / 1.12 + 128.25, gz / 1.12 + 128.25]) > 0.25 {
                 if self.noise.get([gx / 1.1 + 256.0, gz / 1.1 + 256.0]) > 0.5 {
-                    generate_tree(world_position, current_chunk)
+                    generate_tree(world_position, current_chunk, &self.ids)
                 }
             }
         }
-        return Items::Air as u16;
+        return AIR;
     }
 
     #[allow(unused_variables)]
@@ -40,9 +72,12 @@ impl ChunkFilling {
 
         let noise = self.noise.get([gx / 32.0, gy / 32.0, gz / 32.0]);
         if noise > 0.5 {
-            return Items::Air as u16;
+            if gy < LAVA_LEVEL {
+                return self.ids.lava;
+            }
+            return AIR;
         }
-        return Items::Stone as u16;
+        return self.ids.stone;
     }
 
     pub fn fill_block(&self, world_position: WorldPosition, current_chunk: &Chunk, generate_structure: bool) -> Cube {
@@ -50,13 +85,15 @@ impl ChunkFilling {
             return Cube {
                 id: self.fill_caverns(world_position, generate_structure),
                 natural_light_level: MIN_LIGHT_LEVEL,
-                items_light_level: 0,
+                items_light_level: [0; 3],
+                state: 0,
             };
         }
         return Cube {
             id: self.fill_surface(world_position, current_chunk, generate_structure),
             natural_light_level: MIN_LIGHT_LEVEL,
-            items_light_level: 0,
+            items_light_level: [0; 3],
+            state: 0,
         };
     }
 }
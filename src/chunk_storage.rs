@@ -0,0 +1,298 @@
+use crate::{
+    chunk::{Cube, CHUNK_SIZE},
+    items::block_registry,
+    lighting::LightChannel,
+};
+
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// block ids are stored as bit-packed indices into a palette, a chunk with a single block id has no indices at all
+#[derive(Clone)]
+pub struct ChunkStorage {
+    palette: Vec<u16>,
+    bits: u32,
+    words: Vec<u64>,
+    natural_light: LightArray,
+    items_light: [LightArray; 3],
+    states: LightArray, // not a light but stored the same way, most chunks only hold blocks in their default state
+}
+
+#[derive(Clone)]
+pub struct LightArray {
+    uniform: u8,
+    values: Option<Box<[u8]>>,
+}
+
+impl LightArray {
+    pub fn new(uniform: u8) -> Self {
+        Self { uniform, values: None }
+    }
+
+    pub fn from_values(values: Vec<u8>) -> Self {
+        let mut light_array = Self::new(values[0]);
+        if values.iter().any(|value| *value != light_array.uniform) {
+            light_array.values = Some(values.into_boxed_slice());
+        }
+        light_array
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> u8 {
+        match &self.values {
+            Some(values) => values[i],
+            None => self.uniform,
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, i: usize, value: u8) {
+        if self.values.is_none() {
+            if value == self.uniform {
+                return;
+            }
+            self.values = Some(vec![self.uniform; CHUNK_VOLUME].into_boxed_slice());
+        }
+        self.values.as_mut().unwrap()[i] = value;
+    }
+
+    pub fn fill(&mut self, value: u8) {
+        self.uniform = value;
+        self.values = None;
+    }
+}
+
+// the smallest of 0, 1, 2, 4, 8 and 16 bits that indexes the palette, see repack
+fn bits_for(palette_len: usize) -> u32 {
+    let mut bits = 0;
+    while palette_len > 1 << bits {
+        bits = if bits == 0 { 1 } else { bits * 2 };
+    }
+    bits
+}
+
+impl ChunkStorage {
+    pub fn new() -> Self {
+        Self {
+            palette: vec![0],
+            bits: 0,
+            words: Vec::new(),
+            natural_light: LightArray::new(0),
+            items_light: [LightArray::new(0), LightArray::new(0), LightArray::new(0)],
+            states: LightArray::new(0),
+        }
+    }
+
+    // None if the parts could not have been written by this storage, a broken region must not panic later in get_id
+    pub fn from_raw_parts(palette: Vec<u16>, bits: u32, words: Vec<u64>, natural_light: LightArray, items_light: [LightArray; 3], states: LightArray) -> Option<Self> {
+        if palette.is_empty() || palette.len() > 1 << 16 || bits != bits_for(palette.len()) {
+            return None;
+        }
+        let expected_words = if bits == 0 { 0 } else { (CHUNK_VOLUME + (64 / bits) as usize - 1) / (64 / bits) as usize };
+        if words.len() != expected_words || palette.iter().any(|id| *id as usize >= block_registry().len()) {
+            return None;
+        }
+        let storage = Self {
+            palette,
+            bits,
+            words,
+            natural_light,
+            items_light,
+            states,
+        };
+        if (0..CHUNK_VOLUME).any(|i| storage.get_index(i) >= storage.palette.len()) {
+            return None;
+        }
+        Some(storage)
+    }
+
+    pub fn palette(&self) -> &[u16] {
+        &self.palette
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn natural_light(&self) -> &LightArray {
+        &self.natural_light
+    }
+
+    pub fn items_light(&self) -> &[LightArray; 3] {
+        &self.items_light
+    }
+
+    pub fn states(&self) -> &LightArray {
+        &self.states
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    #[inline]
+    fn get_index(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        ((self.words[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    #[inline]
+    fn set_index(&mut self, i: usize, index: usize) {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    // bits stay a power of two so an index never spans two words
+    fn repack(&mut self, bits: u32) {
+        let indices: Vec<usize> = (0..CHUNK_VOLUME).map(|i| self.get_index(i)).collect();
+        self.bits = bits;
+        if bits == 0 {
+            self.words = Vec::new();
+            return;
+        }
+        let per_word = (64 / bits) as usize;
+        self.words = vec![0; (CHUNK_VOLUME + per_word - 1) / per_word];
+        for (i, index) in indices.into_iter().enumerate() {
+            self.set_index(i, index);
+        }
+    }
+
+    #[inline]
+    pub fn get_id(&self, i: usize) -> u16 {
+        self.palette[self.get_index(i)]
+    }
+
+    pub fn set_id(&mut self, i: usize, id: u16) {
+        let index = match self.palette.iter().position(|palette_id| *palette_id == id) {
+            Some(index) => index,
+            None => {
+                self.palette.push(id);
+                if self.palette.len() > 1 << self.bits {
+                    self.repack(bits_for(self.palette.len()));
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits > 0 {
+            self.set_index(i, index);
+        }
+    }
+
+    pub fn fill_id(&mut self, id: u16) {
+        self.palette = vec![id];
+        self.bits = 0;
+        self.words = Vec::new();
+    }
+
+    // drops palette entries that are not used anymore, a chunk back to a single id becomes uniform again
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..CHUNK_VOLUME {
+            used[self.get_index(i)] = true;
+        }
+        if used.iter().all(|used| *used) {
+            return;
+        }
+        let ids: Vec<u16> = (0..CHUNK_VOLUME).map(|i| self.get_id(i)).collect();
+        self.palette = self.palette.iter().zip(used.iter()).filter(|(_, used)| **used).map(|(id, _)| *id).collect();
+        let bits = bits_for(self.palette.len());
+        self.bits = bits;
+        self.words = Vec::new();
+        if bits > 0 {
+            let per_word = (64 / bits) as usize;
+            self.words = vec![0; (CHUNK_VOLUME + per_word - 1) / per_word];
+            for (i, id) in ids.into_iter().enumerate() {
+                let index = self.palette.iter().position(|palette_id| *palette_id == id).unwrap();
+                self.set_index(i, index);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get_natural_light(&self, i: usize) -> u8 {
+        self.natural_light.get(i)
+    }
+
+    #[inline]
+    pub fn set_natural_light(&mut self, i: usize, light_level: u8) {
+        self.natural_light.set(i, light_level);
+    }
+
+    pub fn fill_natural_light(&mut self, light_level: u8) {
+        self.natural_light.fill(light_level);
+    }
+
+    #[inline]
+    pub fn get_items_light(&self, i: usize) -> [u8; 3] {
+        [self.items_light[0].get(i), self.items_light[1].get(i), self.items_light[2].get(i)]
+    }
+
+    #[inline]
+    pub fn set_items_light(&mut self, i: usize, light_level: [u8; 3]) {
+        for (light_array, light_level) in self.items_light.iter_mut().zip(light_level) {
+            light_array.set(i, light_level);
+        }
+    }
+
+    #[inline]
+    pub fn get_state(&self, i: usize) -> u8 {
+        self.states.get(i)
+    }
+
+    #[inline]
+    pub fn set_state(&mut self, i: usize, state: u8) {
+        self.states.set(i, state);
+    }
+
+    #[inline]
+    pub fn get_light(&self, channel: LightChannel, i: usize) -> u8 {
+        match channel {
+            LightChannel::Natural => self.natural_light.get(i),
+            LightChannel::Red => self.items_light[0].get(i),
+            LightChannel::Green => self.items_light[1].get(i),
+            LightChannel::Blue => self.items_light[2].get(i),
+        }
+    }
+
+    #[inline]
+    pub fn set_light(&mut self, channel: LightChannel, i: usize, light_level: u8) {
+        match channel {
+            LightChannel::Natural => self.natural_light.set(i, light_level),
+            LightChannel::Red => self.items_light[0].set(i, light_level),
+            LightChannel::Green => self.items_light[1].set(i, light_level),
+            LightChannel::Blue => self.items_light[2].set(i, light_level),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> Cube {
+        Cube {
+            id: self.get_id(i),
+            natural_light_level: self.get_natural_light(i),
+            items_light_level: self.get_items_light(i),
+            state: self.get_state(i),
+        }
+    }
+
+    pub fn set(&mut self, i: usize, cube: Cube) {
+        self.set_id(i, cube.id);
+        self.set_natural_light(i, cube.natural_light_level);
+        self.set_items_light(i, cube.items_light_level);
+        self.set_state(i, cube.state);
+    }
+
+    // greedy meshing needs a plain slice of voxels
+    pub fn to_cubes(&self) -> Vec<Cube> {
+        (0..CHUNK_VOLUME).map(|i| self.get(i)).collect()
+    }
+}
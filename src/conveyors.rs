@@ -0,0 +1,244 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::{prelude::*, render::primitives::Aabb, utils::HashMap};
+
+use crate::{
+    block_entities::{BlockEntity, BlockEntityKind},
+    chunk::{Chunk, Cube, MeshData},
+    inventory::ItemStack,
+    items::{block_registry, front_face, offset, opposite_face, StateProperty, AIR, FACES},
+    positions::{ChunkPosition, WorldPosition},
+    world::{self, World},
+};
+
+pub const LANES: usize = 2; // left and right of the direction of the belt
+const BELT_STEPS: u32 = 8; // ticks for an item to cross a belt
+const ITEM_SPACING: u32 = 4; // steps between two items of a lane
+const LANE_OFFSET: f32 = 0.25; // distance of a lane from the middle of the belt
+const ITEM_SIZE: f32 = 0.3;
+const ITEM_AO: f32 = 3.0; // no ambient occlusion on the items
+const ITEM_LIGHT: Cube = Cube {
+    id: AIR,
+    natural_light_level: 255,
+    items_light_level: [0; 3],
+    state: 0,
+};
+
+#[derive(Clone, Debug)]
+pub struct BeltItem {
+    pub stack: ItemStack,
+    pub progress: u32, // 0 at the back of the belt, BELT_STEPS at its front
+}
+
+// items go where the player looked while placing the belt, away from its front texture
+#[derive(Clone, Debug, Default)]
+pub struct Conveyor {
+    pub lanes: [Vec<BeltItem>; LANES], // front item first
+}
+
+impl Conveyor {
+    // returns what did not fit, an item only enters a lane once the last one moved far enough
+    pub fn insert(&mut self, lane: usize, stack: ItemStack) -> Option<ItemStack> {
+        if !self.has_room(lane) {
+            return Some(stack);
+        }
+        self.lanes[lane].push(BeltItem { stack, progress: 0 });
+        None
+    }
+
+    pub fn has_room(&self, lane: usize) -> bool {
+        self.lanes[lane].last().map_or(true, |item| item.progress >= ITEM_SPACING)
+    }
+
+    // from the most advanced item of the first lane that has one
+    pub fn extract(&mut self, count: u32) -> Option<ItemStack> {
+        let lane = self.lanes.iter_mut().find(|lane| !lane.is_empty())?;
+        let stack = lane[0].stack.split(count);
+        if lane[0].stack.count == 0 {
+            lane.remove(0);
+        }
+        stack
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|lane| lane.is_empty())
+    }
+
+    pub fn has_item_at_end(&self) -> bool {
+        self.lanes.iter().any(|lane| lane.first().map_or(false, |item| item.progress == BELT_STEPS))
+    }
+
+    fn take_item_at_end(&mut self, lane: usize) -> Option<ItemStack> {
+        let lane = &mut self.lanes[lane];
+        if lane.first()?.progress < BELT_STEPS {
+            return None;
+        }
+        Some(lane.remove(0).stack)
+    }
+
+    // every item moves one step unless the end of the belt or the item ahead stops it, returns true if one moved
+    pub fn tick(&mut self) -> bool {
+        let mut moved = false;
+        for lane in self.lanes.iter_mut() {
+            let mut limit = BELT_STEPS;
+            for item in lane.iter_mut() {
+                if item.progress < limit {
+                    item.progress += 1;
+                    moved = true;
+                }
+                limit = item.progress.saturating_sub(ITEM_SPACING);
+            }
+        }
+        moved
+    }
+}
+
+// index in FACES of the side the items leave a belt by, None if there is no belt there
+fn conveyor_direction(world: &World, world_position: WorldPosition) -> Option<usize> {
+    let (id, state) = world.get_block_state(world_position)?;
+    let item = block_registry().get(id);
+    if item.block_entity != Some(BlockEntityKind::Conveyor) {
+        return None;
+    }
+    Some(opposite_face(front_face(item.state.get(state, StateProperty::Facing))))
+}
+
+// called by block_entities::tick_block_entities once every chunk is released, the next belt or container can be in another chunk
+pub fn hand_off_items(world: &World, mut world_positions: Vec<WorldPosition>) {
+    world_positions.sort_by_key(|world_position| (world_position.x, world_position.y, world_position.z));
+    for world_position in world_positions {
+        let direction = match conveyor_direction(world, world_position) {
+            Some(direction) => direction,
+            None => continue,
+        };
+        let target = offset(world_position, direction);
+        // two belts facing each other would pass the same items back and forth
+        if conveyor_direction(world, target) == Some(opposite_face(direction)) {
+            continue;
+        }
+        for lane in 0..LANES {
+            let stack = world.with_block_entity(world_position, |block_entity| match block_entity {
+                BlockEntity::Conveyor(conveyor) => conveyor.take_item_at_end(lane),
+                _ => None,
+            });
+            let mut rest = stack.flatten();
+            if rest.is_none() {
+                continue;
+            }
+            // a belt keeps the lane of the item, machines and containers take it like any other insertion
+            world.with_block_entity(target, |block_entity| {
+                let stack = rest.take().unwrap();
+                rest = match block_entity {
+                    BlockEntity::Conveyor(conveyor) => conveyor.insert(lane, stack),
+                    BlockEntity::ItemPipe(pipe) => pipe.insert(stack, Some(world_position)),
+                    _ => block_entity.insert(stack),
+                };
+            });
+            if let Some(rest) = rest {
+                world.with_block_entity(world_position, |block_entity| {
+                    if let BlockEntity::Conveyor(conveyor) = block_entity {
+                        conveyor.lanes[lane].insert(0, BeltItem { stack: rest, progress: BELT_STEPS });
+                    }
+                });
+            }
+        }
+    }
+}
+
+// the items of a chunk are merged into one mesh, on belts or in pipes, rebuilt only when one of them moved, came or left
+#[derive(Default)]
+pub struct DrawnItems(HashMap<ChunkPosition, (Vec<(u16, Vec3)>, Entity, Handle<Mesh>)>);
+
+fn push_item(mesh_data: &mut MeshData, id: u16, translation: Vec3) {
+    let item = block_registry().get(id);
+    let textures = item.face_textures(item.default_state);
+    for (face, direction) in FACES.iter().enumerate() {
+        let corners = MeshData::UNIT_CUBE_FACES[face];
+        let uvs = MeshData::face_uvs(face, corners);
+        let positions = corners.map(|corner| [0, 1, 2].map(|axis| (corner[axis] - 0.5) * ITEM_SIZE + translation[axis]));
+        mesh_data.push_face(positions, direction.map(|axis| axis as f32), uvs, textures[face].0 as i32, ITEM_LIGHT, ITEM_AO);
+    }
+}
+
+// items are small cubes drawn on top of the chunk meshes, the mesh of a chunk is rewritten in place so its entity stays
+pub fn draw_items(commands: &mut Commands, world: &World, meshes: &mut Assets<Mesh>, drawn_items: &mut DrawnItems, items: HashMap<ChunkPosition, Vec<(u16, Vec3)>>) {
+    drawn_items.0.retain(|chunk_position, (_, entity, mesh)| {
+        if items.contains_key(chunk_position) {
+            return true;
+        }
+        commands.entity(*entity).despawn();
+        meshes.remove(mesh.clone());
+        false
+    });
+    for (chunk_position, chunk_items) in items {
+        if drawn_items.0.get(&chunk_position).map_or(false, |(drawn, _, _)| *drawn == chunk_items) {
+            continue;
+        }
+        let mut mesh_data = MeshData::default();
+        for (id, translation) in chunk_items.iter() {
+            push_item(&mut mesh_data, *id, *translation);
+        }
+        let mesh = mesh_data.into_mesh().unwrap();
+        match drawn_items.0.get_mut(&chunk_position) {
+            Some((drawn, entity, handle)) => {
+                if let Some(drawn_mesh) = meshes.get_mut(handle) {
+                    *drawn_mesh = mesh;
+                }
+                // the bounds used for frustum culling are computed again from the new mesh
+                commands.entity(*entity).remove::<Aabb>();
+                *drawn = chunk_items;
+            }
+            None => {
+                let handle = meshes.add(mesh);
+                let entity = commands
+                    .spawn_bundle(MaterialMeshBundle {
+                        mesh: handle.clone(),
+                        material: world.material.read().unwrap().clone(),
+                        ..default()
+                    })
+                    .id();
+                drawn_items.0.insert(chunk_position, (chunk_items, entity, handle));
+            }
+        }
+    }
+}
+
+pub fn draw_conveyor_items(mut commands: Commands, world: Res<Arc<RwLock<world::World>>>, mut meshes: ResMut<Assets<Mesh>>, mut drawn_items: Local<DrawnItems>) {
+    let world_read_lock = world.read().unwrap();
+    let chunks: Vec<Arc<RwLock<Chunk>>> = world_read_lock.chunks.read().unwrap().values().cloned().collect();
+    let mut conveyors = Vec::new();
+    for chunk in chunks {
+        let chunk_lock = chunk.read().unwrap();
+        if !chunk_lock.drawn {
+            continue;
+        }
+        for (world_position, block_entity) in chunk_lock.block_entities.read().unwrap().iter() {
+            if let BlockEntity::Conveyor(conveyor) = block_entity {
+                if !conveyor.is_empty() {
+                    conveyors.push((chunk_lock.position, *world_position, conveyor.clone()));
+                }
+            }
+        }
+    }
+
+    let mut items: HashMap<ChunkPosition, Vec<(u16, Vec3)>> = HashMap::new();
+    for (chunk_position, world_position, conveyor) in conveyors {
+        let direction = match conveyor_direction(&world_read_lock, world_position) {
+            Some(direction) => Vec3::new(FACES[direction][0] as f32, 0.0, FACES[direction][2] as f32),
+            None => continue,
+        };
+        // cubes are rendered one unit away from their world position, see positions::translation_to_world_position
+        let top = Vec3::new(world_position.x as f32 + 1.5, world_position.y as f32 + 2.0 + ITEM_SIZE / 2.0, world_position.z as f32 + 1.5);
+        let left = Vec3::Y.cross(direction);
+        let chunk_items = items.entry(chunk_position).or_insert_with(Vec::new);
+        for (lane, belt_items) in conveyor.lanes.iter().enumerate() {
+            let side = if lane == 0 { left } else { -left };
+            for belt_item in belt_items {
+                let along = belt_item.progress as f32 / BELT_STEPS as f32 - 0.5;
+                chunk_items.push((belt_item.stack.id, top + direction * along + side * LANE_OFFSET));
+            }
+        }
+    }
+
+    draw_items(&mut commands, &world_read_lock, &mut meshes, &mut drawn_items, items);
+}
@@ -0,0 +1,252 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use bevy::{asset::FileAssetIo, utils::HashMap};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use crate::{
+    inventory::{take_from_slot, ItemStack, Slot},
+    items::{block_registry, BlockRegistry},
+};
+
+pub const CRAFTING_SIZE: usize = 3; // width and height of the biggest grid
+const RECIPE_REGISTRY_PATH: &str = "recipes.ron"; // relative to the assets folder
+
+static RECIPE_REGISTRY: OnceCell<Arc<RecipeRegistry>> = OnceCell::new();
+
+pub fn recipe_registry() -> &'static Arc<RecipeRegistry> {
+    RECIPE_REGISTRY.get_or_init(|| {
+        let path = FileAssetIo::get_base_path().join("assets").join(RECIPE_REGISTRY_PATH);
+        match RecipeRegistry::load(&path, block_registry()) {
+            Ok(recipe_registry) => Arc::new(recipe_registry),
+            Err(error) => panic!("Cannot load the recipe registry {}: {}", path.display(), error),
+        }
+    })
+}
+
+// the ids an ingredient accepts, a single one or every item of a tag
+struct Ingredient(Vec<u16>);
+
+impl Ingredient {
+    fn matches(&self, id: u16) -> bool {
+        self.0.contains(&id)
+    }
+}
+
+enum RecipeShape {
+    Shaped { width: usize, height: usize, cells: Vec<Option<Ingredient>> }, // row by row, from the top
+    Shapeless(Vec<Ingredient>),
+}
+
+pub struct Recipe {
+    shape: RecipeShape,
+    pub result: (u16, u32), // item id and count
+}
+
+impl Recipe {
+    fn matches(&self, input: &CraftingInput) -> bool {
+        match &self.shape {
+            RecipeShape::Shaped { width, height, cells } => {
+                *width == input.width
+                    && *height == input.height
+                    && cells.iter().zip(input.cells.iter()).all(|(cell, id)| match (cell, id) {
+                        (Some(ingredient), Some(id)) => ingredient.matches(*id),
+                        (None, None) => true,
+                        _ => false,
+                    })
+            }
+            RecipeShape::Shapeless(ingredients) => {
+                let ids: Vec<u16> = input.cells.iter().flatten().copied().collect();
+                ids.len() == ingredients.len() && assign(&ids, ingredients, &mut vec![false; ingredients.len()])
+            }
+        }
+    }
+}
+
+// tags can make an item fit several ingredients, so every ingredient is tried for every item until all are used
+fn assign(ids: &[u16], ingredients: &[Ingredient], used: &mut [bool]) -> bool {
+    let (id, rest) = match ids.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+    for i in 0..ingredients.len() {
+        if !used[i] && ingredients[i].matches(*id) {
+            used[i] = true;
+            if assign(rest, ingredients, used) {
+                return true;
+            }
+            used[i] = false;
+        }
+    }
+    false
+}
+
+// a crafting grid cut down to the box around its items, so a recipe matches wherever it is placed in the grid
+struct CraftingInput {
+    width: usize,
+    height: usize,
+    cells: Vec<Option<u16>>,
+}
+
+impl CraftingInput {
+    fn new(grid: &[Option<u16>], grid_width: usize) -> Option<Self> {
+        let filled: Vec<(usize, usize)> = grid.iter().enumerate().filter(|(_, id)| id.is_some()).map(|(i, _)| (i % grid_width, i / grid_width)).collect();
+        let min_x = filled.iter().map(|(x, _)| *x).min()?;
+        let max_x = filled.iter().map(|(x, _)| *x).max()?;
+        let min_y = filled.iter().map(|(_, y)| *y).min()?;
+        let max_y = filled.iter().map(|(_, y)| *y).max()?;
+        let mut cells = Vec::with_capacity((max_x - min_x + 1) * (max_y - min_y + 1));
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                cells.push(grid[y * grid_width + x]);
+            }
+        }
+        Some(Self {
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+            cells,
+        })
+    }
+
+    // the first item row by row and how many items there are, see RecipeRegistry::index
+    fn key(&self) -> (u16, usize) {
+        let first = self.cells.iter().flatten().next().copied().unwrap_or_default();
+        (first, self.cells.iter().flatten().count())
+    }
+}
+
+pub struct RecipeRegistry {
+    recipes: Vec<Recipe>,
+    // recipes by the first item of a matching grid and its number of items, a lookup only checks a handful of recipes
+    index: HashMap<(u16, usize), Vec<usize>>,
+}
+
+impl RecipeRegistry {
+    pub fn load(path: &PathBuf, block_registry: &BlockRegistry) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let file: RecipeFile = ron::from_str(&text).map_err(|error| error.to_string())?;
+        Self::from_definitions(file, block_registry)
+    }
+
+    fn from_definitions(file: RecipeFile, block_registry: &BlockRegistry) -> Result<Self, String> {
+        let mut tags: HashMap<String, Vec<u16>> = HashMap::new();
+        for (tag, names) in file.tags {
+            let mut ids = Vec::with_capacity(names.len());
+            for name in names {
+                ids.push(block_registry.id(&name).ok_or_else(|| format!("tag \"{}\" contains unknown block \"{}\"", tag, name))?);
+            }
+            tags.insert(tag, ids);
+        }
+        let ingredient = |definition: &IngredientDefinition| -> Result<Ingredient, String> {
+            match definition {
+                IngredientDefinition::Item(name) => block_registry.id(name).map(|id| Ingredient(vec![id])).ok_or_else(|| format!("unknown block \"{}\"", name)),
+                IngredientDefinition::Tag(tag) => tags.get(tag).map(|ids| Ingredient(ids.clone())).ok_or_else(|| format!("unknown tag \"{}\"", tag)),
+            }
+        };
+
+        let mut recipes = Vec::with_capacity(file.recipes.len());
+        for (i, definition) in file.recipes.into_iter().enumerate() {
+            let (shape, (result_name, result_count)) = match definition {
+                RecipeDefinition::Shaped { pattern, key, result } => {
+                    let height = pattern.len();
+                    let width = pattern.first().map_or(0, |row| row.chars().count());
+                    if width == 0 || width > CRAFTING_SIZE || height > CRAFTING_SIZE || pattern.iter().any(|row| row.chars().count() != width) {
+                        return Err(format!("recipe {} needs rows of the same width, up to {} by {}", i, CRAFTING_SIZE, CRAFTING_SIZE));
+                    }
+                    let mut cells = Vec::with_capacity(width * height);
+                    for symbol in pattern.iter().flat_map(|row| row.chars()) {
+                        cells.push(match symbol {
+                            ' ' => None,
+                            _ => {
+                                let definition = key.get(&symbol).ok_or_else(|| format!("recipe {} has no key for '{}'", i, symbol))?;
+                                Some(ingredient(definition).map_err(|error| format!("recipe {}: {}", i, error))?)
+                            }
+                        });
+                    }
+                    // a pattern is matched against the box around the items of the grid, so it must fill its own box
+                    let input = CraftingInput::new(&cells.iter().map(|cell| cell.as_ref().map(|_| 0)).collect::<Vec<_>>(), width);
+                    if input.map_or(true, |input| input.width != width || input.height != height) {
+                        return Err(format!("recipe {} has empty rows or columns on its border", i));
+                    }
+                    (RecipeShape::Shaped { width, height, cells }, result)
+                }
+                RecipeDefinition::Shapeless { ingredients, result } => {
+                    if ingredients.is_empty() || ingredients.len() > CRAFTING_SIZE * CRAFTING_SIZE {
+                        return Err(format!("recipe {} needs 1 to {} ingredients", i, CRAFTING_SIZE * CRAFTING_SIZE));
+                    }
+                    let ingredients = ingredients.iter().map(&ingredient).collect::<Result<Vec<_>, _>>().map_err(|error| format!("recipe {}: {}", i, error))?;
+                    (RecipeShape::Shapeless(ingredients), result)
+                }
+            };
+            let result_id = block_registry.id(&result_name).ok_or_else(|| format!("recipe {} makes unknown block \"{}\"", i, result_name))?;
+            if result_count == 0 {
+                return Err(format!("recipe {} makes nothing", i));
+            }
+            recipes.push(Recipe { shape, result: (result_id, result_count) });
+        }
+
+        let mut index: HashMap<(u16, usize), Vec<usize>> = HashMap::new();
+        for (i, recipe) in recipes.iter().enumerate() {
+            // any ingredient of a shapeless recipe can come first in the grid
+            let (first_ids, count): (Vec<u16>, usize) = match &recipe.shape {
+                RecipeShape::Shaped { cells, .. } => (cells.iter().flatten().next().map_or(Vec::new(), |ingredient| ingredient.0.clone()), cells.iter().flatten().count()),
+                RecipeShape::Shapeless(ingredients) => (ingredients.iter().flat_map(|ingredient| ingredient.0.iter().copied()).collect(), ingredients.len()),
+            };
+            for id in first_ids {
+                let recipes = index.entry((id, count)).or_default();
+                if recipes.last() != Some(&i) {
+                    recipes.push(i);
+                }
+            }
+        }
+        Ok(Self { recipes, index })
+    }
+
+    // grid is read row by row, in file order the first recipe that matches wins
+    pub fn find(&self, grid: &[Option<u16>], grid_width: usize) -> Option<&Recipe> {
+        let input = CraftingInput::new(grid, grid_width)?;
+        let candidates = self.index.get(&input.key())?;
+        candidates.iter().map(|i| &self.recipes[*i]).find(|recipe| recipe.matches(&input))
+    }
+
+    pub fn result(&self, grid: &[Slot], grid_width: usize) -> Option<ItemStack> {
+        let ids: Vec<Option<u16>> = grid.iter().map(|slot| slot.as_ref().map(|stack| stack.id)).collect();
+        let (id, count) = self.find(&ids, grid_width)?.result;
+        Some(ItemStack::new(id, count))
+    }
+
+    // takes one item of every slot of the grid
+    pub fn craft(&self, grid: &mut [Slot], grid_width: usize) -> Option<ItemStack> {
+        let result = self.result(grid, grid_width)?;
+        for slot in grid.iter_mut() {
+            take_from_slot(slot, 1);
+        }
+        Some(result)
+    }
+}
+
+#[derive(Deserialize)]
+struct RecipeFile {
+    #[serde(default)]
+    tags: HashMap<String, Vec<String>>,
+    recipes: Vec<RecipeDefinition>,
+}
+
+#[derive(Deserialize)]
+enum RecipeDefinition {
+    Shaped {
+        pattern: Vec<String>,
+        key: HashMap<char, IngredientDefinition>,
+        result: (String, u32),
+    },
+    Shapeless {
+        ingredients: Vec<IngredientDefinition>,
+        result: (String, u32),
+    },
+}
+
+#[derive(Deserialize)]
+enum IngredientDefinition {
+    Item(String),
+    Tag(String),
+}
@@ -0,0 +1,123 @@
+use std::{
+    f32::consts::TAU,
+    sync::{Arc, RwLock},
+};
+
+use bevy::prelude::*;
+
+use crate::{game_material::GameMaterial, world};
+
+const DAY_LENGTH: f32 = 1200.0; // seconds for a whole day
+const START_TIME_OF_DAY: f32 = 0.3;
+const MIN_SKY_BRIGHTNESS: f32 = 0.15; // moonlight
+const TWILIGHT_HEIGHT: f32 = 0.2; // height of the sun under which the sky starts to darken
+const SUN_ILLUMINANCE: f32 = 32000.0;
+const MOON_ILLUMINANCE: f32 = 400.0;
+const DAY_SKY_COLOR: [f32; 3] = [0.47, 0.66, 1.0];
+const NIGHT_SKY_COLOR: [f32; 3] = [0.01, 0.01, 0.04];
+
+// time of day goes from 0 to 1, 0 is midnight, 0.25 sunrise, 0.5 noon and 0.75 sunset
+pub struct WorldClock {
+    pub time_of_day: f32,
+    pub day_length: f32,
+    pub days: u32,
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self {
+            time_of_day: START_TIME_OF_DAY,
+            day_length: DAY_LENGTH,
+            days: 0,
+        }
+    }
+}
+
+impl WorldClock {
+    pub fn advance(&mut self, delta_seconds: f32) {
+        self.time_of_day += delta_seconds / self.day_length;
+        while self.time_of_day >= 1.0 {
+            self.time_of_day -= 1.0;
+            self.days += 1;
+        }
+    }
+
+    // unit vector pointing from the ground to the sun, slightly tilted so the sun never stands exactly at the zenith
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time_of_day - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), 0.2).normalize()
+    }
+
+    // multiplier applied to the sky light in the fragment shader
+    pub fn sky_brightness(&self) -> f32 {
+        let t = ((self.sun_direction().y + TWILIGHT_HEIGHT) / (2.0 * TWILIGHT_HEIGHT)).clamp(0.0, 1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        MIN_SKY_BRIGHTNESS + (1.0 - MIN_SKY_BRIGHTNESS) * t
+    }
+
+    fn daylight(&self) -> f32 {
+        (self.sky_brightness() - MIN_SKY_BRIGHTNESS) / (1.0 - MIN_SKY_BRIGHTNESS)
+    }
+
+    pub fn sky_color(&self) -> Color {
+        let t = self.daylight();
+        let [r, g, b] = [0, 1, 2].map(|c| NIGHT_SKY_COLOR[c] + (DAY_SKY_COLOR[c] - NIGHT_SKY_COLOR[c]) * t);
+        Color::rgb(r, g, b)
+    }
+}
+
+// the directional light follows the sun during the day and the moon during the night
+#[derive(Component)]
+pub struct SkyLight;
+
+pub fn spawn_sky_light(mut commands: Commands, clock: Res<WorldClock>) {
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: SUN_ILLUMINANCE,
+                shadows_enabled: false,
+                ..default()
+            },
+            transform: sky_light_transform(&clock),
+            ..default()
+        })
+        .insert(SkyLight);
+}
+
+fn sky_light_transform(clock: &WorldClock) -> Transform {
+    let sun_direction = clock.sun_direction();
+    let light_direction = if sun_direction.y >= 0.0 { sun_direction } else { -sun_direction };
+    Transform::default().looking_at(-light_direction, Vec3::Y)
+}
+
+pub fn advance_world_clock(time: Res<Time>, mut clock: ResMut<WorldClock>) {
+    clock.advance(time.delta_seconds());
+}
+
+// only the sky brightness uniform changes with the time, chunk meshes keep their baked sky light
+pub fn update_sky(
+    clock: Res<WorldClock>,
+    world: Res<Arc<RwLock<world::World>>>,
+    mut materials: ResMut<Assets<GameMaterial>>,
+    mut clear_color: ResMut<ClearColor>,
+    mut sky_light: Query<(&mut Transform, &mut DirectionalLight), With<SkyLight>>,
+) {
+    if !clock.is_changed() {
+        return;
+    }
+    let sky_brightness = clock.sky_brightness();
+    let world_read_lock = world.read().unwrap();
+    for material_handle in [world_read_lock.material.read().unwrap().clone(), world_read_lock.translucent_material.read().unwrap().clone()] {
+        // avoid touching the material when the change is invisible, every modification re-uploads its bind group
+        let needs_update = materials.get(&material_handle).map_or(false, |material| (material.sky_brightness - sky_brightness).abs() >= 1.0 / 255.0);
+        if needs_update {
+            materials.get_mut(&material_handle).unwrap().sky_brightness = sky_brightness;
+        }
+    }
+
+    clear_color.0 = clock.sky_color();
+    for (mut transform, mut directional_light) in sky_light.iter_mut() {
+        *transform = sky_light_transform(&clock);
+        directional_light.illuminance = if clock.sun_direction().y >= 0.0 { SUN_ILLUMINANCE } else { MOON_ILLUMINANCE };
+    }
+}
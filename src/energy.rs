@@ -0,0 +1,299 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use bevy::utils::{HashMap, HashSet};
+use serde::Deserialize;
+
+use crate::{
+    items::{block_registry, offset, FACES},
+    positions::WorldPosition,
+    region::ByteReader,
+};
+
+const ENERGY_FILE: &str = "energy.dat"; // in the world directory, next to the regions
+const ENERGY_MAGIC: &[u8; 4] = b"FTBE";
+const ENERGY_FORMAT_VERSION: u8 = 1;
+const MAX_TRANSFER_PER_TICK: u32 = 200; // energy a node gives or takes each tick
+
+// blocks.ron gives the part a block plays in an energy network, blocks of any role connect to their 6 neighbours
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EnergyRole {
+    Generator { capacity: u32 }, // its block entity fills its buffer, the network empties it
+    Cable,                       // only connects
+    Consumer { capacity: u32 },  // the network fills its buffer, its block entity empties it
+    Storage { capacity: u32 },   // keeps what generators have left and gives it back when they fall short
+}
+
+impl EnergyRole {
+    pub fn capacity(&self) -> u32 {
+        match self {
+            EnergyRole::Generator { capacity } | EnergyRole::Consumer { capacity } | EnergyRole::Storage { capacity } => *capacity,
+            EnergyRole::Cable => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EnergyBuffer {
+    pub stored: u32,
+    pub capacity: u32,
+}
+
+struct EnergyNode {
+    id: u16, // block id, what the save keeps to find the role back
+    role: EnergyRole,
+    network: u32,
+    buffer: EnergyBuffer,
+}
+
+// the graph of every energy block of the world, loaded or not, so a network keeps working across chunk borders and unloads
+#[derive(Default)]
+pub struct EnergyNetworks {
+    nodes: HashMap<WorldPosition, EnergyNode>,
+    networks: HashMap<u32, BTreeSet<WorldPosition>>, // kept in order so distribute needs no sort
+    next_network: u32,
+}
+
+impl EnergyNetworks {
+    fn neighbour_networks(&self, world_position: WorldPosition) -> Vec<(WorldPosition, u32)> {
+        (0..FACES.len())
+            .filter_map(|face| {
+                let neighbour = offset(world_position, face);
+                self.nodes.get(&neighbour).map(|node| (neighbour, node.network))
+            })
+            .collect()
+    }
+
+    // joins the network of the neighbours, merged into the biggest one when the block links several
+    pub fn add(&mut self, world_position: WorldPosition, id: u16, role: EnergyRole) {
+        self.remove(world_position);
+        let mut networks: Vec<u32> = self.neighbour_networks(world_position).into_iter().map(|(_, network)| network).collect();
+        networks.sort_unstable();
+        networks.dedup();
+        let network = match networks.iter().max_by_key(|network| self.networks[*network].len()) {
+            Some(network) => *network,
+            None => {
+                self.next_network += 1;
+                self.networks.insert(self.next_network, BTreeSet::new());
+                self.next_network
+            }
+        };
+        for other in networks.into_iter().filter(|other| *other != network) {
+            let positions = self.networks.remove(&other).unwrap_or_default();
+            for position in positions.iter() {
+                self.nodes.get_mut(position).unwrap().network = network;
+            }
+            self.networks.get_mut(&network).unwrap().extend(positions);
+        }
+        let buffer = EnergyBuffer { stored: 0, capacity: role.capacity() };
+        self.nodes.insert(world_position, EnergyNode { id, role, network, buffer });
+        self.networks.get_mut(&network).unwrap().insert(world_position);
+    }
+
+    // the energy of the block is lost, the parts it was the only link between become networks of their own
+    pub fn remove(&mut self, world_position: WorldPosition) {
+        let network = match self.nodes.remove(&world_position) {
+            Some(node) => node.network,
+            None => return,
+        };
+        let positions = self.networks.get_mut(&network).unwrap();
+        positions.remove(&world_position);
+        if positions.is_empty() {
+            self.networks.remove(&network);
+            return;
+        }
+        let neighbours = self.neighbour_networks(world_position);
+        if neighbours.len() < 2 {
+            return;
+        }
+        // the part of the first neighbour keeps the network, every neighbour it does not reach starts a new one
+        let reached = self.connected(neighbours[0].0);
+        for (neighbour, _) in neighbours.into_iter().skip(1) {
+            if reached.contains(&neighbour) || self.nodes[&neighbour].network != network {
+                continue;
+            }
+            self.next_network += 1;
+            let part = self.connected(neighbour);
+            for position in part.iter() {
+                self.nodes.get_mut(position).unwrap().network = self.next_network;
+                self.networks.get_mut(&network).unwrap().remove(position);
+            }
+            self.networks.insert(self.next_network, part.into_iter().collect());
+        }
+    }
+
+    fn connected(&self, start: WorldPosition) -> HashSet<WorldPosition> {
+        let mut reached = HashSet::new();
+        reached.insert(start);
+        let mut to_visit = vec![start];
+        while let Some(position) = to_visit.pop() {
+            for (neighbour, _) in self.neighbour_networks(position) {
+                if reached.insert(neighbour) {
+                    to_visit.push(neighbour);
+                }
+            }
+        }
+        reached
+    }
+
+    // energy.dat is only written on exit, after a crash the saved chunks win: the nodes between min and max become the energy blocks found there
+    pub fn sync(&mut self, min: WorldPosition, max: WorldPosition, blocks: Vec<(WorldPosition, u16)>) {
+        let blocks: HashMap<WorldPosition, u16> = blocks.into_iter().collect();
+        let inside = |position: &WorldPosition| (min.x..=max.x).contains(&position.x) && (min.y..=max.y).contains(&position.y) && (min.z..=max.z).contains(&position.z);
+        let stale: Vec<WorldPosition> = self
+            .nodes
+            .iter()
+            .filter(|(position, node)| inside(position) && blocks.get(position) != Some(&node.id))
+            .map(|(position, _)| *position)
+            .collect();
+        for position in stale {
+            self.remove(position);
+        }
+        for (position, id) in blocks {
+            if let (false, Some(role)) = (self.nodes.contains_key(&position), block_registry().get(id).energy) {
+                self.add(position, id, role);
+            }
+        }
+    }
+
+    pub fn buffer(&self, world_position: WorldPosition) -> Option<EnergyBuffer> {
+        self.nodes.get(&world_position).map(|node| node.buffer)
+    }
+
+    pub fn buffer_mut(&mut self, world_position: WorldPosition) -> Option<&mut EnergyBuffer> {
+        self.nodes.get_mut(&world_position).map(|node| &mut node.buffer)
+    }
+
+    // called by ticks::tick_world after the block entities, networks share no node so only the order of the nodes keeps the world deterministic
+    pub fn distribute(&mut self) {
+        let networks = std::mem::take(&mut self.networks);
+        for positions in networks.values() {
+            self.distribute_in(positions);
+        }
+        self.networks = networks;
+    }
+
+    // generators feed the consumers, storages make up for what is missing or keep what is left
+    fn distribute_in(&mut self, positions: &BTreeSet<WorldPosition>) {
+        let (mut generators, mut consumers, mut storages) = (Vec::new(), Vec::new(), Vec::new());
+        for position in positions {
+            match self.nodes[position].role {
+                EnergyRole::Generator { .. } => generators.push(*position),
+                EnergyRole::Consumer { .. } => consumers.push(*position),
+                EnergyRole::Storage { .. } => storages.push(*position),
+                EnergyRole::Cable => {}
+            }
+        }
+
+        let mut pool = self.take(&generators, u32::MAX);
+        let wanted: u32 = consumers.iter().map(|position| room(&self.nodes[position].buffer)).sum();
+        if pool < wanted {
+            pool += self.take(&storages, wanted - pool);
+        }
+        pool = self.give(&consumers, pool);
+        pool = self.give(&storages, pool);
+        // what nobody could take stays in the generators
+        self.give(&generators, pool);
+    }
+
+    // returns what was taken
+    fn take(&mut self, positions: &[WorldPosition], mut wanted: u32) -> u32 {
+        let mut taken = 0;
+        for position in positions {
+            let buffer = &mut self.nodes.get_mut(position).unwrap().buffer;
+            let amount = buffer.stored.min(MAX_TRANSFER_PER_TICK).min(wanted);
+            buffer.stored -= amount;
+            taken += amount;
+            wanted -= amount;
+        }
+        taken
+    }
+
+    // returns what is left
+    fn give(&mut self, positions: &[WorldPosition], mut pool: u32) -> u32 {
+        for position in positions {
+            let buffer = &mut self.nodes.get_mut(position).unwrap().buffer;
+            let amount = room(buffer).min(pool);
+            buffer.stored += amount;
+            pool -= amount;
+        }
+        pool
+    }
+
+    // nodes are saved with their block id, the role comes back from the registry and the networks are rebuilt
+    pub fn load(directory: &Path) -> Self {
+        let mut energy_networks = Self::default();
+        let data = match fs::read(directory.join(ENERGY_FILE)) {
+            Ok(data) => data,
+            Err(_) => return energy_networks,
+        };
+        if data.len() < 5 || &data[0..4] != ENERGY_MAGIC || data[4] != ENERGY_FORMAT_VERSION {
+            println!("Ignoring the energy networks of {}, unknown format", directory.display());
+            return energy_networks;
+        }
+        let mut reader = ByteReader::new(&data[5..]);
+        let nb_nodes = reader.read_u32().unwrap_or(0);
+        for _ in 0..nb_nodes {
+            let (world_position, id, stored) = match read_node(&mut reader) {
+                Some(node) => node,
+                None => break,
+            };
+            // the block may have lost its role since the save
+            if id as usize >= block_registry().len() {
+                continue;
+            }
+            let role = match block_registry().get(id).energy {
+                Some(role) => role,
+                None => continue,
+            };
+            energy_networks.add(world_position, id, role);
+            let buffer = energy_networks.buffer_mut(world_position).unwrap();
+            buffer.stored = stored.min(buffer.capacity);
+        }
+        energy_networks
+    }
+
+    pub fn save(&self, directory: &Path) {
+        let mut data = Vec::new();
+        data.extend_from_slice(ENERGY_MAGIC);
+        data.push(ENERGY_FORMAT_VERSION);
+        data.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        for (world_position, node) in self.nodes.iter() {
+            for coordinate in [world_position.x, world_position.y, world_position.z] {
+                data.extend_from_slice(&coordinate.to_le_bytes());
+            }
+            data.extend_from_slice(&node.id.to_le_bytes());
+            data.extend_from_slice(&node.buffer.stored.to_le_bytes());
+        }
+        // same as regions, written next to the file first so a crash never leaves half of it
+        let path = directory.join(ENERGY_FILE);
+        let temporary_path = path.with_extension("dat.tmp");
+        let result = fs::create_dir_all(directory).and_then(|_| {
+            let mut file = File::create(&temporary_path)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+            fs::rename(&temporary_path, &path)
+        });
+        if let Err(error) = result {
+            println!("Cannot save the energy networks to {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn read_node(reader: &mut ByteReader) -> Option<(WorldPosition, u16, u32)> {
+    let world_position = WorldPosition {
+        x: reader.read_i32()?,
+        y: reader.read_i32()?,
+        z: reader.read_i32()?,
+    };
+    Some((world_position, reader.read_u16()?, reader.read_u32()?))
+}
+
+// what a buffer can still take this tick
+fn room(buffer: &EnergyBuffer) -> u32 {
+    (buffer.capacity - buffer.stored).min(MAX_TRANSFER_PER_TICK)
+}
@@ -1,7 +1,21 @@
+use std::sync::{Arc, RwLock};
+
 use bevy::{input::mouse::MouseMotion, prelude::*};
 
+use crate::{
+    block_entities::{BlockEntity, BlockEntityKind},
+    inventory::{ItemStack, PlayerInventory},
+    items::{block_registry, Item, StateProperty, AIR, FACES},
+    positions::WorldPosition,
+    raycast::TargetedBlock,
+    world,
+};
+
 const FIXED_DELTA_TIME: f32 = 1.0 / 144.0;
 const SPEED: f32 = 100.0;
+const PLAYER_WIDTH: f32 = 0.6;
+const PLAYER_EYE_HEIGHT: f32 = 1.6;
+const PLAYER_HEAD_HEIGHT: f32 = 0.2;
 
 #[derive(Component)]
 pub struct GameCamera {
@@ -15,7 +29,7 @@ impl Default for GameCamera {
     }
 }
 
-pub fn handle_keyboard(keys: Res<Input<KeyCode>>, mut camera: Query<&mut Transform, With<Camera>>, mut window: ResMut<Windows>) {
+pub fn handle_keyboard(keys: Res<Input<KeyCode>>, inventory: Res<PlayerInventory>, mut camera: Query<&mut Transform, With<Camera>>, mut window: ResMut<Windows>) {
     if keys.pressed(KeyCode::Z) {
         for mut transform in camera.iter_mut() {
             let forward = transform.forward();
@@ -50,7 +64,7 @@ pub fn handle_keyboard(keys: Res<Input<KeyCode>>, mut camera: Query<&mut Transfo
             transform.translation.y -= FIXED_DELTA_TIME * SPEED;
         }
     }
-    if keys.just_pressed(KeyCode::Escape) {
+    if keys.just_pressed(KeyCode::Escape) && !inventory.is_open {
         for window in window.iter_mut() {
             window.set_cursor_lock_mode(!window.cursor_locked());
             window.set_cursor_visibility(!window.cursor_visible());
@@ -58,7 +72,12 @@ pub fn handle_keyboard(keys: Res<Input<KeyCode>>, mut camera: Query<&mut Transfo
     }
 }
 
-pub fn handle_mouse_motion(mut mouse_motion_events: EventReader<MouseMotion>, mut camera: Query<(&mut Transform, &mut GameCamera)>) {
+pub fn handle_mouse_motion(mut mouse_motion_events: EventReader<MouseMotion>, windows: Res<Windows>, mut camera: Query<(&mut Transform, &mut GameCamera)>) {
+    // the free cursor is for the inventory
+    if !windows.get_primary().map_or(false, |window| window.cursor_locked()) {
+        mouse_motion_events.clear();
+        return;
+    }
     for event in mouse_motion_events.iter() {
         for (mut transform, mut game_camera) in camera.iter_mut() {
             game_camera.yaw += event.delta.x * 0.1;
@@ -76,3 +95,130 @@ pub fn handle_mouse_motion(mut mouse_motion_events: EventReader<MouseMotion>, mu
         }
     }
 }
+
+fn overlaps_player(position: WorldPosition, eye: Vec3) -> bool {
+    // cubes are rendered one unit away from their world position, see positions::translation_to_world_position
+    let cube_min = Vec3::new(position.x as f32, position.y as f32, position.z as f32) + Vec3::ONE;
+    let cube_max = cube_min + Vec3::ONE;
+    let player_min = Vec3::new(eye.x - PLAYER_WIDTH / 2.0, eye.y - PLAYER_EYE_HEIGHT, eye.z - PLAYER_WIDTH / 2.0);
+    let player_max = Vec3::new(eye.x + PLAYER_WIDTH / 2.0, eye.y + PLAYER_HEAD_HEIGHT, eye.z + PLAYER_WIDTH / 2.0);
+    cube_min.cmplt(player_max).all() && player_min.cmplt(cube_max).all()
+}
+
+// logs follow the face they are placed against, blocks with a front turn it towards the player
+fn placement_state(item: &Item, face: usize, look_direction: Vec3) -> u8 {
+    let mut state = item.default_state;
+    let axis = match face {
+        0 | 3 => 1,
+        2 | 5 => 2,
+        _ => 0,
+    };
+    state = item.state.with(state, StateProperty::Axis, axis);
+    let facing = if look_direction.x.abs() > look_direction.z.abs() {
+        if look_direction.x > 0.0 { 3 } else { 1 }
+    } else if look_direction.z > 0.0 {
+        2
+    } else {
+        0
+    };
+    item.state.with(state, StateProperty::Facing, facing)
+}
+
+// what a broken block gives, the block itself keeps its block entity if there is something in it
+fn block_drops(item: &Item, block_entity: Option<BlockEntity>) -> Vec<ItemStack> {
+    let mut block_entity = block_entity.filter(|block_entity| !block_entity.is_empty());
+    item.drops
+        .iter()
+        .map(|(id, count)| {
+            let mut stack = ItemStack::new(*id, *count);
+            if *id == item.id && block_entity.is_some() {
+                stack.count = 1;
+                stack.data = block_entity.take().map(Box::new);
+            }
+            stack
+        })
+        .collect()
+}
+
+pub fn handle_mouse_buttons(
+    buttons: Res<Input<MouseButton>>,
+    mut windows: ResMut<Windows>,
+    world: Res<Arc<RwLock<world::World>>>,
+    targeted_block: Res<TargetedBlock>,
+    mut inventory: ResMut<PlayerInventory>,
+    camera: Query<&Transform, With<GameCamera>>,
+) {
+    let block_registry = block_registry();
+    if !windows.get_primary().map_or(false, |window| window.cursor_locked()) {
+        return;
+    }
+    let hit = match &targeted_block.0 {
+        Some(hit) => hit,
+        None => return,
+    };
+    if buttons.just_pressed(MouseButton::Left) {
+        let world_read_lock = world.read().unwrap();
+        let item = match world_read_lock.get_block(hit.position) {
+            Some(id) if block_registry.get(id).hardness >= 0.0 => block_registry.get(id),
+            _ => return,
+        };
+        let block_entity = world_read_lock.block_entity(hit.position);
+        world_read_lock.set_block(hit.position, AIR);
+        for stack in block_drops(item, block_entity) {
+            if let Some(rest) = inventory.insert(stack) {
+                println!("Lost {} {}, the inventory is full", rest.count, block_registry.get(rest.id).name);
+            }
+        }
+    } else if buttons.just_pressed(MouseButton::Right) {
+        // right click opens machines and containers, or switches blocks like lamps, instead of placing against them
+        let world_read_lock = world.read().unwrap();
+        if let Some((id, state)) = world_read_lock.get_block_state(hit.position) {
+            let item = block_registry.get(id);
+            // belts take the item in hand, they have no inventory to open
+            if item.block_entity == Some(BlockEntityKind::Conveyor) {
+                if let Some(stack) = inventory.take_selected(1) {
+                    let rest = world_read_lock.with_block_entity(hit.position, |block_entity| block_entity.insert(stack.clone())).unwrap_or(Some(stack));
+                    if let Some(rest) = rest {
+                        inventory.insert(rest);
+                    }
+                }
+                return;
+            }
+            if item.block_entity.is_some() {
+                inventory.open(Some(hit.position));
+                if let Some(window) = windows.get_primary_mut() {
+                    window.set_cursor_lock_mode(false);
+                    window.set_cursor_visibility(true);
+                }
+                return;
+            }
+            if item.state.has(StateProperty::Lit) {
+                let lit = item.state.get(state, StateProperty::Lit);
+                world_read_lock.set_block_state(hit.position, id, item.state.with(state, StateProperty::Lit, 1 - lit));
+                return;
+            }
+        }
+        drop(world_read_lock);
+        let position = WorldPosition {
+            x: hit.position.x + FACES[hit.face][0] as i32,
+            y: hit.position.y + FACES[hit.face][1] as i32,
+            z: hit.position.z + FACES[hit.face][2] as i32,
+        };
+        let item = match inventory.selected_stack() {
+            Some(stack) => block_registry.get(stack.id),
+            None => return,
+        };
+        if item.is_solid && camera.iter().any(|transform| overlaps_player(position, transform.translation)) {
+            return;
+        }
+        let look_direction = camera.iter().next().map_or(Vec3::NEG_Z, |transform| transform.forward());
+        let world_read_lock = world.read().unwrap();
+        if world_read_lock.get_block(position).map_or(false, |id| block_registry.is_replaceable(id)) {
+            world_read_lock.set_block_state(position, item.id, placement_state(item, hit.face, look_direction));
+            // a container placed back gets its content back
+            if let Some(data) = inventory.take_selected(1).and_then(|stack| stack.data) {
+                world_read_lock.with_block_entity(position, |block_entity| *block_entity = *data);
+            }
+        }
+    }
+}
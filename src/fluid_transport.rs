@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+
+use bevy::utils::HashSet;
+
+use crate::{
+    block_entities::BlockEntity,
+    items::{block_registry, offset, AIR, FACES},
+    positions::WorldPosition,
+    world::World,
+};
+
+pub const BUCKET: u32 = 1000; // millibuckets in a source block
+pub const TANK_CAPACITY: u32 = 16 * BUCKET;
+pub const FLUID_PIPE_CAPACITY: u32 = BUCKET / 4;
+pub const PUMP_CAPACITY: u32 = 2 * BUCKET;
+const MAX_FLOW: u32 = 200; // millibuckets crossing a side each tick
+const PUMP_TICKS: u32 = 20; // ticks between two source blocks
+const PUMP_RANGE: usize = 64; // fluid cubes searched for a source under a pump
+const POSITIVE_FACES: [usize; 3] = [3, 4, 5]; // indices in FACES, each pair of neighbours is balanced once
+const BELOW: usize = 1;
+
+// an amount of a single fluid, the fluid is the id of its block
+#[derive(Clone, Debug)]
+pub struct FluidTank {
+    pub fluid: Option<u16>, // None when empty
+    pub amount: u32,        // in millibuckets
+    pub capacity: u32,
+}
+
+impl FluidTank {
+    pub fn new(capacity: u32) -> Self {
+        Self { fluid: None, amount: 0, capacity }
+    }
+
+    pub fn room_for(&self, fluid: u16) -> u32 {
+        match self.fluid {
+            Some(other) if other != fluid => 0,
+            _ => self.capacity - self.amount,
+        }
+    }
+
+    // returns what was filled
+    pub fn fill(&mut self, fluid: u16, amount: u32) -> u32 {
+        let amount = amount.min(self.room_for(fluid));
+        if amount > 0 {
+            self.fluid = Some(fluid);
+            self.amount += amount;
+        }
+        amount
+    }
+
+    // returns what was drained
+    pub fn drain(&mut self, amount: u32) -> u32 {
+        let amount = amount.min(self.amount);
+        self.amount -= amount;
+        if self.amount == 0 {
+            self.fluid = None;
+        }
+        amount
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pump {
+    pub tank: FluidTank,
+    pub cooldown: u32, // ticks before the next source block
+}
+
+impl Default for Pump {
+    fn default() -> Self {
+        Self {
+            tank: FluidTank::new(PUMP_CAPACITY),
+            cooldown: 0,
+        }
+    }
+}
+
+impl Pump {
+    pub fn is_ready(&self) -> bool {
+        self.cooldown == 0 && self.tank.capacity - self.tank.amount >= BUCKET
+    }
+
+    pub fn tick(&mut self) -> bool {
+        if self.cooldown == 0 {
+            return false;
+        }
+        self.cooldown -= 1;
+        true
+    }
+}
+
+// the nearest source of the fluid under the pump, through the cubes of that fluid
+fn find_source(world: &World, start: WorldPosition) -> Option<(WorldPosition, u16)> {
+    let id = world.get_block(start)?;
+    block_registry().get(id).fluid?;
+    let mut visited = HashSet::new();
+    visited.insert(start);
+    let mut to_visit = VecDeque::from([start]);
+    while let Some(world_position) = to_visit.pop_front() {
+        match world.get_block_state(world_position) {
+            Some((other, 0)) if other == id => return Some((world_position, id)),
+            Some((other, _)) if other == id => {}
+            _ => continue,
+        }
+        for face in 0..FACES.len() {
+            let neighbour = offset(world_position, face);
+            if visited.len() < PUMP_RANGE && visited.insert(neighbour) {
+                to_visit.push_back(neighbour);
+            }
+        }
+    }
+    None
+}
+
+// called by block_entities::tick_block_entities once every chunk is released, removing a source sets a block
+pub fn pump_fluids(world: &World, mut world_positions: Vec<WorldPosition>) {
+    world_positions.sort_by_key(|world_position| (world_position.x, world_position.y, world_position.z));
+    for world_position in world_positions {
+        let fluid = world.with_block_entity(world_position, |block_entity| match block_entity {
+            BlockEntity::Pump(pump) => {
+                pump.cooldown = PUMP_TICKS;
+                Some(pump.tank.fluid)
+            }
+            _ => None,
+        });
+        let fluid = match fluid.flatten() {
+            Some(fluid) => fluid,
+            None => continue,
+        };
+        let (source, id) = match find_source(world, offset(world_position, BELOW)) {
+            Some(source) => source,
+            None => continue,
+        };
+        // a pump keeps a single fluid until it is empty
+        if fluid.map_or(false, |fluid| fluid != id) {
+            continue;
+        }
+        world.set_block(source, AIR);
+        world.with_block_entity(world_position, |block_entity| {
+            if let BlockEntity::Pump(pump) = block_entity {
+                pump.tank.fill(id, BUCKET);
+            }
+        });
+    }
+}
+
+// a fluid tank read in place, with the part its block entity plays
+struct Holder {
+    tank: FluidTank,
+    pump: bool,
+    pipe: bool,
+    generator: bool,
+}
+
+fn holder(block_entity: &BlockEntity) -> Option<Holder> {
+    Some(Holder {
+        tank: block_entity.fluid_tank()?.clone(),
+        pump: matches!(block_entity, BlockEntity::Pump(_)),
+        pipe: matches!(block_entity, BlockEntity::FluidPipe(_)),
+        generator: matches!(block_entity, BlockEntity::FluidGenerator(_)),
+    })
+}
+
+// pumps only give, machines only take, tanks and pipes do both
+fn gives(holder: &Holder) -> bool {
+    !holder.generator
+}
+
+fn takes(holder: &Holder, fluid: u16) -> bool {
+    !holder.pump && (!holder.generator || block_registry().get(fluid).fluid_burn_time > 0)
+}
+
+// what goes from a to b so both are filled to the same part of their capacity, like the pressure of connected vessels
+fn flow(a: &FluidTank, b: &FluidTank) -> u32 {
+    let (a_amount, a_capacity, b_amount, b_capacity) = (a.amount as u64, a.capacity as u64, b.amount as u64, b.capacity as u64);
+    if a_amount * b_capacity <= b_amount * a_capacity {
+        return 0;
+    }
+    (((a_amount * b_capacity - b_amount * a_capacity) / (a_capacity + b_capacity)) as u32).min(MAX_FLOW)
+}
+
+// called by block_entities::tick_block_entities once every chunk is released, neighbours can be in another chunk
+pub fn balance_fluids(world: &World, mut world_positions: Vec<WorldPosition>) {
+    world_positions.sort_by_key(|world_position| (world_position.x, world_position.y, world_position.z));
+    for world_position in world_positions {
+        for face in POSITIVE_FACES {
+            let neighbour = offset(world_position, face);
+            // both tanks are read before anything moves
+            let (first, second) = match (world.read_block_entity(world_position, holder).flatten(), world.read_block_entity(neighbour, holder).flatten()) {
+                (Some(first), Some(second)) => (first, second),
+                _ => continue,
+            };
+            // fluid goes to the side filled to the smaller part of its capacity, a pump always gives
+            let (from, to, giver, taker) = if first.pump || (!second.pump && flow(&first.tank, &second.tank) > 0) {
+                (world_position, neighbour, &first, &second)
+            } else {
+                (neighbour, world_position, &second, &first)
+            };
+            let fluid = match giver.tank.fluid {
+                Some(fluid) => fluid,
+                None => continue,
+            };
+            // pumps, and pipes giving to anything but a pipe, push all the taker has room for instead of sharing, so tanks fill up
+            let amount = if giver.pump || (giver.pipe && !taker.pipe) {
+                giver.tank.amount.min(MAX_FLOW)
+            } else {
+                flow(&giver.tank, &taker.tank)
+            };
+            let amount = amount.min(taker.tank.room_for(fluid));
+            if amount == 0 || !gives(giver) || !takes(taker, fluid) {
+                continue;
+            }
+            world.with_block_entity(from, |block_entity| block_entity.fluid_tank_mut().map(|tank| tank.drain(amount)));
+            world.with_block_entity(to, |block_entity| block_entity.fluid_tank_mut().map(|tank| tank.fill(fluid, amount)));
+        }
+    }
+}
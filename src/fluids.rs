@@ -0,0 +1,182 @@
+use block_mesh::ndshape::ConstShape;
+
+use crate::{
+    chunk::{ChunkShape, Cube, MeshData, CHUNK_SIZE},
+    items::{block_registry, offset, Fluid, AIR, FACES},
+    positions::WorldPosition,
+    world::World,
+};
+
+// the state of a fluid cube is its level: 0 for a source, then 1 to flow_distance away from it, or FALLING
+pub const FALLING: u8 = 8; // level of a fluid fed from above, it spreads sideways like a source
+const HORIZONTAL_FACES: [usize; 4] = [0, 2, 3, 5]; // indices in FACES
+pub const FLOW_FACES: [usize; 5] = [0, 1, 2, 3, 5]; // every side a fluid can go to, all but the top
+const ABOVE: usize = 4;
+const BELOW: usize = 1;
+const FLUID_AO: f32 = 3.0; // no ambient occlusion on fluid faces
+
+// one step of the flow, every change schedules the changed cube and its neighbours again, see ticks
+pub fn update_fluid(world: &World, world_position: WorldPosition) {
+    let (id, level) = match world.get_block_state(world_position) {
+        Some(block) => block,
+        None => return,
+    };
+    let fluid = match block_registry().get(id).fluid {
+        Some(fluid) => fluid,
+        None => return,
+    };
+
+    if level != 0 {
+        match fed_level(world, world_position, id, &fluid) {
+            None => {
+                world.set_block(world_position, AIR);
+                return;
+            }
+            Some(fed_level) if fed_level != level => {
+                world.set_block_state(world_position, id, fed_level);
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    // falling first, a fluid only spreads sideways once it lies on something
+    let below = offset(world_position, BELOW);
+    match world.get_block_state(below) {
+        Some((below_id, _)) if below_id == AIR => {
+            world.set_block_state(below, id, FALLING);
+            return;
+        }
+        Some((below_id, below_level)) if below_id == id => {
+            if below_level != 0 && below_level != FALLING {
+                world.set_block_state(below, id, FALLING);
+            }
+            if below_level != 0 {
+                return;
+            }
+        }
+        None => return,
+        _ => {}
+    }
+
+    let spread_level = if level == 0 || level == FALLING { 1 } else { level + 1 };
+    if spread_level > fluid.flow_distance {
+        return;
+    }
+    for face in HORIZONTAL_FACES {
+        let neighbour = offset(world_position, face);
+        match world.get_block_state(neighbour) {
+            Some((neighbour_id, _)) if neighbour_id == AIR => {
+                world.set_block_state(neighbour, id, spread_level);
+            }
+            Some((neighbour_id, neighbour_level)) if neighbour_id == id && neighbour_level != 0 && neighbour_level != FALLING && neighbour_level > spread_level => {
+                world.set_block_state(neighbour, id, spread_level);
+            }
+            _ => {}
+        }
+    }
+}
+
+// level a flowing cube should have from what feeds it, None if nothing does anymore
+fn fed_level(world: &World, world_position: WorldPosition, id: u16, fluid: &Fluid) -> Option<u8> {
+    if world.get_block(offset(world_position, ABOVE)) == Some(id) {
+        return Some(FALLING);
+    }
+    let mut nb_sources = 0;
+    let mut lowest_level = None;
+    for face in HORIZONTAL_FACES {
+        if let Some((neighbour_id, neighbour_level)) = world.get_block_state(offset(world_position, face)) {
+            if neighbour_id != id {
+                continue;
+            }
+            if neighbour_level == 0 {
+                nb_sources += 1;
+            }
+            let neighbour_level = if neighbour_level == FALLING { 0 } else { neighbour_level };
+            lowest_level = Some(lowest_level.map_or(neighbour_level, |lowest: u8| lowest.min(neighbour_level)));
+        }
+    }
+    if fluid.renews && nb_sources >= 2 {
+        let below = world.get_block_state(offset(world_position, BELOW));
+        if below.map_or(false, |(below_id, below_level)| (below_id == id && below_level == 0) || block_registry().get(below_id).is_solid) {
+            return Some(0);
+        }
+    }
+    lowest_level.map(|level| level + 1).filter(|level| *level <= fluid.flow_distance)
+}
+
+// height of the surface of a fluid cube, a source is a bit lower than a full cube like in most voxel games
+pub fn fluid_height(level: u8, fluid: &Fluid) -> f32 {
+    if level == 0 || level == FALLING {
+        return 8.0 / 9.0;
+    }
+    8.0 / 9.0 * (1.0 - level as f32 / (fluid.flow_distance as f32 + 1.0))
+}
+
+// fluids are empty for greedy meshing, their faces are built here with the top corners lowered to the surrounding levels
+pub fn mesh_fluids(cubes: &[Cube], opaque_mesh_data: &mut MeshData, translucent_mesh_data: &mut MeshData, translucent_offset: f32) {
+    for z in 1..CHUNK_SIZE - 1 {
+        for y in 1..CHUNK_SIZE - 1 {
+            for x in 1..CHUNK_SIZE - 1 {
+                let cube = cubes[ChunkShape::linearize([x, y, z]) as usize];
+                let item = block_registry().get(cube.id);
+                let fluid = match &item.fluid {
+                    Some(fluid) => fluid,
+                    None => continue,
+                };
+                let (mesh_data, offset) = if item.is_translucent { (&mut *translucent_mesh_data, translucent_offset) } else { (&mut *opaque_mesh_data, 0.0) };
+                let corner_heights = [[0, 0], [0, 1], [1, 0], [1, 1]].map(|[cx, cz]| corner_height(cubes, [x, y, z], [cx, cz], cube.id, fluid));
+                let height = |cx: usize, cz: usize| corner_heights[cx * 2 + cz];
+
+                for (face, direction) in FACES.iter().enumerate() {
+                    let neighbour = cubes[ChunkShape::linearize([
+                        (x as i32 + direction[0] as i32) as u32,
+                        (y as i32 + direction[1] as i32) as u32,
+                        (z as i32 + direction[2] as i32) as u32,
+                    ]) as usize];
+                    if neighbour.id == cube.id {
+                        continue;
+                    }
+                    let neighbour_item = block_registry().get(neighbour.id);
+                    // the top face stays visible under a cube as the surface is lower than a full cube
+                    if neighbour.id != AIR && !neighbour_item.is_transparent && neighbour_item.fluid.is_none() && face != ABOVE {
+                        continue;
+                    }
+                    let corners = MeshData::UNIT_CUBE_FACES[face].map(|[cx, cy, cz]| [cx, if cy > 0.0 { height(cx as usize, cz as usize) } else { 0.0 }, cz]);
+                    let positions = corners.map(|[cx, cy, cz]| [x as f32 + cx + offset, y as f32 + cy + offset, z as f32 + cz + offset]);
+                    let uvs = MeshData::face_uvs(face, corners);
+                    // the top face is lit by the cube above, or by the fluid itself when it is covered
+                    let light_cube = if face == ABOVE && neighbour.id != AIR && !neighbour_item.is_transparent { cube } else { neighbour };
+                    mesh_data.push_face(
+                        positions,
+                        [direction[0] as f32, direction[1] as f32, direction[2] as f32],
+                        uvs,
+                        item.textures[face] as i32,
+                        light_cube,
+                        FLUID_AO,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn corner_height(cubes: &[Cube], [x, y, z]: [u32; 3], [cx, cz]: [u32; 2], id: u16, fluid: &Fluid) -> f32 {
+    let mut total = 0.0;
+    let mut count = 0.0;
+    for nx in [x + cx - 1, x + cx] {
+        for nz in [z + cz - 1, z + cz] {
+            let cube = cubes[ChunkShape::linearize([nx, y, nz]) as usize];
+            if cube.id != id {
+                continue;
+            }
+            // fluid flowing down the corner fills it up to the next cube
+            if cubes[ChunkShape::linearize([nx, y + 1, nz]) as usize].id == id {
+                return 1.0;
+            }
+            total += fluid_height(cube.state, fluid);
+            count += 1.0;
+        }
+    }
+    total / count
+}
@@ -1,5 +1,5 @@
 use bevy::{
-    pbr::{MaterialPipeline, MaterialPipelineKey},
+    pbr::{MaterialPipeline, MaterialPipelineKey, MeshPipelineKey},
     prelude::*,
     reflect::TypeUuid,
     render::{
@@ -8,7 +8,7 @@ use bevy::{
     },
 };
 
-use crate::chunk::{ATTRIBUTE_LAYER, ATTRIBUTE_LIGHT_LEVEL, ATTRIBUTE_AO};
+use crate::chunk::{ATTRIBUTE_AO, ATTRIBUTE_BLOCK_LIGHT_LEVEL, ATTRIBUTE_LAYER, ATTRIBUTE_SKY_LIGHT_LEVEL};
 
 #[derive(AsBindGroup, Debug, Clone, TypeUuid)]
 #[uuid = "9c5a0ddf-1eaf-41b4-9832-ed736fd26af3"]
@@ -16,6 +16,9 @@ pub struct GameMaterial {
     #[texture(0, dimension = "2d_array")]
     #[sampler(1)]
     pub array_texture: Handle<Image>,
+    #[uniform(2)]
+    pub sky_brightness: f32, // follows the world clock, multiplies the sky light only
+    pub alpha_mode: AlphaMode, // Blend for the translucent chunk meshes
 }
 
 impl Material for GameMaterial {
@@ -27,16 +30,26 @@ impl Material for GameMaterial {
         "Shaders/vertex.wgsl".into()
     }
 
-    fn specialize(_pipeline: &MaterialPipeline<Self>, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayout, _key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError> {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(_pipeline: &MaterialPipeline<Self>, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayout, key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError> {
         let vertex_layout = layout.get_layout(&[
             Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
             Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
             Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
             ATTRIBUTE_LAYER.at_shader_location(3),
-            ATTRIBUTE_LIGHT_LEVEL.at_shader_location(4),
+            ATTRIBUTE_SKY_LIGHT_LEVEL.at_shader_location(4),
             ATTRIBUTE_AO.at_shader_location(5),
+            ATTRIBUTE_BLOCK_LIGHT_LEVEL.at_shader_location(6),
         ])?;
         descriptor.vertex.buffers = vec![vertex_layout];
+        if key.mesh_key.contains(MeshPipelineKey::TRANSPARENT_MAIN_PASS) {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("TRANSLUCENT".to_string());
+            }
+        }
         Ok(())
     }
 }
@@ -1,12 +1,18 @@
 use block_mesh::{MergeVoxel, Voxel, VoxelVisibility};
 
-use crate::{chunk::Cube, items};
+use crate::{
+    chunk::Cube,
+    items::{block_registry, AIR},
+};
 
+// opaque pass, translucent cubes are drawn by the second pass so they must not hide anything here
+// fluids are meshed apart in both passes, see fluids::mesh_fluids
 impl Voxel for Cube {
     fn get_visibility(&self) -> VoxelVisibility {
-        if self.id == 0 {
+        let item = block_registry().get(self.id);
+        if self.id == AIR || item.is_translucent || item.fluid.is_some() {
             VoxelVisibility::Empty
-        } else if items::ITEMS[self.id as usize].is_transparent == true {
+        } else if item.is_transparent == true {
             VoxelVisibility::Translucent
         } else {
             VoxelVisibility::Opaque
@@ -15,14 +21,46 @@ impl Voxel for Cube {
 }
 
 impl MergeVoxel for Cube {
-    type MergeValue = u16;
-    type MergeValueFacingNeighbour = u8;
+    type MergeValue = (u16, u8);
+    type MergeValueFacingNeighbour = [u8; 4];
 
+    // the state can rotate the textures
     fn merge_value(&self) -> Self::MergeValue {
-        self.id
+        (self.id, self.state)
     }
 
     fn merge_value_facing_neighbour(&self) -> Self::MergeValueFacingNeighbour {
-        self.natural_light_level + self.items_light_level
+        let [red, green, blue] = self.items_light_level;
+        [self.natural_light_level, red, green, blue]
     }
-}
\ No newline at end of file
+}
+
+// translucent pass, every other cube hides the translucent faces it touches
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct TranslucentPassCube(pub Cube);
+
+impl Voxel for TranslucentPassCube {
+    fn get_visibility(&self) -> VoxelVisibility {
+        let item = block_registry().get(self.0.id);
+        if self.0.id == AIR || item.fluid.is_some() {
+            VoxelVisibility::Empty
+        } else if item.is_translucent {
+            VoxelVisibility::Translucent
+        } else {
+            VoxelVisibility::Opaque
+        }
+    }
+}
+
+impl MergeVoxel for TranslucentPassCube {
+    type MergeValue = (u16, u8);
+    type MergeValueFacingNeighbour = [u8; 4];
+
+    fn merge_value(&self) -> Self::MergeValue {
+        self.0.merge_value()
+    }
+
+    fn merge_value_facing_neighbour(&self) -> Self::MergeValueFacingNeighbour {
+        self.0.merge_value_facing_neighbour()
+    }
+}
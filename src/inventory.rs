@@ -0,0 +1,252 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+
+use crate::{
+    block_entities::BlockEntity,
+    crafting::{recipe_registry, CRAFTING_SIZE},
+    items::block_registry,
+    positions::WorldPosition,
+};
+
+pub const HOTBAR_SLOTS: usize = 9;
+pub const INVENTORY_SLOTS: usize = 36; // the hotbar is the first row
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+#[derive(Clone, Debug)]
+pub struct ItemStack {
+    pub id: u16,
+    pub count: u32,
+    pub data: Option<Box<BlockEntity>>, // a broken machine or container keeps its block entity, such stacks never stack
+}
+
+impl ItemStack {
+    pub fn new(id: u16, count: u32) -> Self {
+        Self { id, count, data: None }
+    }
+
+    pub fn max_count(&self) -> u32 {
+        if self.data.is_some() {
+            1
+        } else {
+            block_registry().get(self.id).stack_size
+        }
+    }
+
+    pub fn stacks_with(&self, other: &ItemStack) -> bool {
+        self.id == other.id && self.data.is_none() && other.data.is_none()
+    }
+
+    // moves what fits of other on this stack, returns the rest
+    pub fn merge(&mut self, mut other: ItemStack) -> Option<ItemStack> {
+        if !self.stacks_with(&other) {
+            return Some(other);
+        }
+        let moved = other.count.min(self.max_count().saturating_sub(self.count));
+        self.count += moved;
+        other.count -= moved;
+        if other.count == 0 {
+            None
+        } else {
+            Some(other)
+        }
+    }
+
+    // takes up to count items off the stack, the data goes with the last item
+    pub fn split(&mut self, count: u32) -> Option<ItemStack> {
+        let taken = count.min(self.count);
+        if taken == 0 {
+            return None;
+        }
+        self.count -= taken;
+        let data = if self.count == 0 { self.data.take() } else { None };
+        Some(ItemStack { id: self.id, count: taken, data })
+    }
+}
+
+pub type Slot = Option<ItemStack>; // never holds a stack of 0 items
+
+// returns what did not fit
+pub fn add_to_slot(slot: &mut Slot, stack: ItemStack) -> Option<ItemStack> {
+    match slot {
+        None => {
+            let mut stack = stack;
+            let rest = stack.split(stack.count.saturating_sub(stack.max_count()));
+            *slot = Some(stack);
+            rest
+        }
+        Some(slot_stack) => slot_stack.merge(stack),
+    }
+}
+
+// whether add_to_slot would take at least one item of the stack
+pub fn slot_has_room(slot: &Slot, stack: &ItemStack) -> bool {
+    slot.as_ref().map_or(true, |slot_stack| slot_stack.stacks_with(stack) && slot_stack.count < slot_stack.max_count())
+}
+
+pub fn take_from_slot(slot: &mut Slot, count: u32) -> Option<ItemStack> {
+    let stack = slot.as_mut()?;
+    let taken = stack.split(count);
+    if stack.count == 0 {
+        *slot = None;
+    }
+    taken
+}
+
+// stacks of the same item are filled before empty slots are used, returns what did not fit
+pub fn insert_into_slots(slots: &mut [Slot], stack: ItemStack) -> Option<ItemStack> {
+    let mut rest = Some(stack);
+    for slot in slots.iter_mut().filter(|slot| slot.is_some()) {
+        rest = add_to_slot(slot, rest?);
+    }
+    for slot in slots.iter_mut().filter(|slot| slot.is_none()) {
+        rest = add_to_slot(slot, rest?);
+    }
+    rest
+}
+
+// left click takes or puts the whole stack, right click takes half of it or puts a single item
+pub fn click_slot(slot: &mut Slot, held: &mut Slot, right_click: bool, accepts: bool) {
+    match held.take() {
+        None => {
+            let count = match slot {
+                Some(stack) if right_click => (stack.count + 1) / 2,
+                Some(stack) => stack.count,
+                None => 0,
+            };
+            *held = take_from_slot(slot, count);
+        }
+        Some(held_stack) if !accepts => *held = Some(held_stack),
+        Some(mut held_stack) => {
+            let fits = slot.as_ref().map_or(true, |stack| stack.stacks_with(&held_stack));
+            if !fits {
+                // different items swap
+                *held = slot.replace(held_stack);
+            } else if right_click {
+                let one = held_stack.split(1).unwrap();
+                if let Some(one) = add_to_slot(slot, one) {
+                    held_stack.merge(one);
+                }
+                *held = if held_stack.count == 0 { None } else { Some(held_stack) };
+            } else {
+                *held = add_to_slot(slot, held_stack);
+            }
+        }
+    }
+}
+
+pub struct PlayerInventory {
+    pub slots: Vec<Slot>,
+    pub selected: usize, // hotbar slot used by the mouse buttons
+    pub held: Slot,      // stack carried by the cursor while the inventory is open
+    pub is_open: bool,
+    pub container: Option<WorldPosition>, // block entity shown next to the inventory
+    pub crafting: Vec<Slot>,              // crafting grid, row by row
+}
+
+impl Default for PlayerInventory {
+    fn default() -> Self {
+        Self {
+            slots: vec![None; INVENTORY_SLOTS],
+            selected: 0,
+            held: None,
+            is_open: false,
+            container: None,
+            crafting: vec![None; CRAFTING_SIZE * CRAFTING_SIZE],
+        }
+    }
+}
+
+impl PlayerInventory {
+    pub fn selected_stack(&self) -> Option<&ItemStack> {
+        self.slots[self.selected].as_ref()
+    }
+
+    pub fn take_selected(&mut self, count: u32) -> Option<ItemStack> {
+        take_from_slot(&mut self.slots[self.selected], count)
+    }
+
+    // returns what did not fit
+    pub fn insert(&mut self, stack: ItemStack) -> Option<ItemStack> {
+        insert_into_slots(&mut self.slots, stack)
+    }
+
+    pub fn open(&mut self, container: Option<WorldPosition>) {
+        self.is_open = true;
+        self.container = container;
+    }
+
+    // the result goes to the held stack, only if it can take all of it
+    pub fn craft(&mut self) {
+        let result = match recipe_registry().result(&self.crafting, CRAFTING_SIZE) {
+            Some(result) => result,
+            None => return,
+        };
+        let fits = self.held.as_ref().map_or(true, |held| held.stacks_with(&result) && held.count + result.count <= held.max_count());
+        if fits {
+            recipe_registry().craft(&mut self.crafting, CRAFTING_SIZE);
+            add_to_slot(&mut self.held, result);
+        }
+    }
+
+    // the held stack and the crafting grid go back in the inventory when it is closed
+    pub fn close(&mut self) {
+        self.is_open = false;
+        self.container = None;
+        let stacks: Vec<ItemStack> = self.held.take().into_iter().chain(self.crafting.iter_mut().filter_map(|slot| slot.take())).collect();
+        for stack in stacks {
+            if let Some(rest) = self.insert(stack) {
+                println!("Lost {} {}, the inventory is full", rest.count, block_registry().get(rest.id).name);
+            }
+        }
+    }
+}
+
+pub fn handle_hotbar_selection(keys: Res<Input<KeyCode>>, mut mouse_wheel_events: EventReader<MouseWheel>, mut inventory: ResMut<PlayerInventory>) {
+    for (i, key) in HOTBAR_KEYS.iter().enumerate() {
+        if keys.just_pressed(*key) {
+            inventory.selected = i;
+        }
+    }
+    for event in mouse_wheel_events.iter() {
+        if event.y > 0.0 {
+            inventory.selected = (inventory.selected + HOTBAR_SLOTS - 1) % HOTBAR_SLOTS;
+        } else if event.y < 0.0 {
+            inventory.selected = (inventory.selected + 1) % HOTBAR_SLOTS;
+        }
+    }
+}
+
+// K gives a stack of every block for testing, what does not fit is lost
+pub fn give_creative_kit(keys: Res<Input<KeyCode>>, mut inventory: ResMut<PlayerInventory>) {
+    if !keys.just_pressed(KeyCode::K) {
+        return;
+    }
+    let block_registry = block_registry();
+    for id in 1..block_registry.len() as u16 {
+        inventory.insert(ItemStack::new(id, block_registry.get(id).stack_size));
+    }
+}
+
+// E opens the inventory and frees the cursor to move stacks around, Escape closes it too
+pub fn toggle_inventory(keys: Res<Input<KeyCode>>, mut inventory: ResMut<PlayerInventory>, mut windows: ResMut<Windows>) {
+    if keys.just_pressed(KeyCode::E) && !inventory.is_open {
+        inventory.open(None);
+    } else if (keys.just_pressed(KeyCode::E) || keys.just_pressed(KeyCode::Escape)) && inventory.is_open {
+        inventory.close();
+    } else {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(!inventory.is_open);
+        window.set_cursor_visibility(inventory.is_open);
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContext};
+
+use crate::{
+    block_entities::{BlockEntity, ELECTRIC_SMELTING_TICKS, SMELTING_TICKS},
+    crafting::{recipe_registry, CRAFTING_SIZE},
+    inventory::{click_slot, ItemStack, PlayerInventory, Slot, HOTBAR_SLOTS, INVENTORY_SLOTS},
+    items::block_registry,
+    world,
+};
+
+const SLOT_SIZE: f32 = 52.0;
+const SLOTS_PER_ROW: usize = 9;
+
+#[derive(Clone, Copy)]
+enum SlotClick {
+    Player(usize, bool), // slot index and whether it was a right click
+    Container(usize, bool),
+    Crafting(usize, bool),
+    CraftingResult,
+    Filter(usize), // side of a pipe, by index in FACES
+}
+
+fn slot_button(ui: &mut egui::Ui, slot: &Slot, selected: bool) -> egui::Response {
+    let text = match slot {
+        Some(stack) => format!("{}\n{}", block_registry().get(stack.id).name, stack.count),
+        None => String::new(),
+    };
+    let stroke = if selected {
+        egui::Stroke::new(2.0, egui::Color32::WHITE)
+    } else {
+        egui::Stroke::new(1.0, egui::Color32::DARK_GRAY)
+    };
+    ui.add(egui::Button::new(egui::RichText::new(text).small()).min_size(egui::vec2(SLOT_SIZE, SLOT_SIZE)).stroke(stroke))
+}
+
+fn slot_grid(ui: &mut egui::Ui, id: &str, slots: &[Slot], row_width: usize, selected: Option<usize>, mut on_click: impl FnMut(usize, bool)) {
+    egui::Grid::new(id).spacing([2.0, 2.0]).show(ui, |ui| {
+        for (i, slot) in slots.iter().enumerate() {
+            let response = slot_button(ui, slot, selected == Some(i));
+            if response.clicked() {
+                on_click(i, false);
+            } else if response.secondary_clicked() {
+                on_click(i, true);
+            }
+            if (i + 1) % row_width == 0 {
+                ui.end_row();
+            }
+        }
+    });
+}
+
+// the hotbar is always drawn, the rest of the inventory and the opened container only while the inventory is open
+pub fn draw_inventory(mut egui_context: ResMut<EguiContext>, mut inventory: ResMut<PlayerInventory>, world: Res<Arc<RwLock<world::World>>>) {
+    let ctx = egui_context.ctx_mut();
+    let mut clicks = Vec::new();
+
+    egui::Area::new("hotbar").anchor(egui::Align2::CENTER_BOTTOM, [0.0, -10.0]).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            for (i, slot) in inventory.slots[..HOTBAR_SLOTS].iter().enumerate() {
+                slot_button(ui, slot, i == inventory.selected);
+            }
+        });
+    });
+    if !inventory.is_open {
+        return;
+    }
+
+    // a copy of the container, clicks are applied to the real one once the windows are drawn
+    let container = inventory.container.and_then(|world_position| world.read().unwrap().block_entity(world_position));
+    let energy = inventory.container.and_then(|world_position| world.read().unwrap().energy.read().unwrap().buffer(world_position));
+    let energy_label = energy.map_or(String::from("not connected"), |energy| format!("{}/{} energy", energy.stored, energy.capacity));
+    if inventory.container.is_some() && container.is_none() {
+        inventory.container = None;
+    }
+
+    egui::Window::new("Inventory").collapsible(false).resizable(false).anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]).show(ctx, |ui| {
+        if let Some(block_entity) = &container {
+            match block_entity {
+                BlockEntity::Chest(chest) => {
+                    ui.label("Chest");
+                    slot_grid(ui, "container", &chest.slots, SLOTS_PER_ROW, None, |i, right_click| clicks.push(SlotClick::Container(i, right_click)));
+                }
+                BlockEntity::Furnace(furnace) => {
+                    ui.label(format!("Furnace: input, fuel and output, {}% smelted", furnace.progress * 100 / SMELTING_TICKS));
+                    slot_grid(ui, "container", &furnace.slots, SLOTS_PER_ROW, None, |i, right_click| clicks.push(SlotClick::Container(i, right_click)));
+                }
+                BlockEntity::Conveyor(_) => {}
+                BlockEntity::Generator(generator) => {
+                    ui.label(format!("Generator: fuel, {}, {}", if generator.burn_time_left > 0 { "burning" } else { "idle" }, energy_label));
+                    slot_grid(ui, "container", &generator.slots, SLOTS_PER_ROW, None, |i, right_click| clicks.push(SlotClick::Container(i, right_click)));
+                }
+                BlockEntity::ItemPipe(pipe) => {
+                    ui.label(format!(
+                        "{}: filters of the -x, -y, -z, +x, +y and +z sides, {} items inside",
+                        if pipe.extracts { "Extraction pipe" } else { "Item pipe" },
+                        pipe.items.len()
+                    ));
+                    ui.label("Click with an item to let only it cross a side, with an empty hand to clear");
+                    let filters: Vec<Slot> = pipe.filters.iter().map(|filter| filter.map(|id| ItemStack::new(id, 1))).collect();
+                    slot_grid(ui, "container", &filters, SLOTS_PER_ROW, None, |i, _| clicks.push(SlotClick::Filter(i)));
+                }
+                BlockEntity::Tank(_) | BlockEntity::FluidPipe(_) | BlockEntity::Pump(_) | BlockEntity::FluidGenerator(_) => {
+                    let name = match block_entity {
+                        BlockEntity::Tank(_) => "Tank",
+                        BlockEntity::FluidPipe(_) => "Fluid pipe",
+                        BlockEntity::Pump(_) => "Pump",
+                        _ => "Fluid generator",
+                    };
+                    let tank = block_entity.fluid_tank().unwrap();
+                    let fluid = tank.fluid.map_or("empty", |fluid| block_registry().get(fluid).name.as_str());
+                    ui.label(format!("{}: {}, {}/{} mB", name, fluid, tank.amount, tank.capacity));
+                    if let BlockEntity::FluidGenerator(fluid_generator) = block_entity {
+                        ui.label(format!("{}, {}", if fluid_generator.burn_time_left > 0 { "burning" } else { "idle" }, energy_label));
+                    }
+                }
+                BlockEntity::ElectricFurnace(electric_furnace) => {
+                    ui.label(format!(
+                        "Electric furnace: input and output, {}% smelted, {}",
+                        electric_furnace.progress * 100 / ELECTRIC_SMELTING_TICKS,
+                        energy_label
+                    ));
+                    slot_grid(ui, "container", &electric_furnace.slots, SLOTS_PER_ROW, None, |i, right_click| clicks.push(SlotClick::Container(i, right_click)));
+                }
+            }
+            ui.separator();
+        } else {
+            // the crafting grid takes the place of the container
+            ui.horizontal(|ui| {
+                slot_grid(ui, "crafting", &inventory.crafting, CRAFTING_SIZE, None, |i, right_click| clicks.push(SlotClick::Crafting(i, right_click)));
+                ui.label("=>");
+                let result = recipe_registry().result(&inventory.crafting, CRAFTING_SIZE);
+                let response = slot_button(ui, &result, false);
+                if response.clicked() || response.secondary_clicked() {
+                    clicks.push(SlotClick::CraftingResult);
+                }
+            });
+            ui.separator();
+        }
+        // the hotbar is the last row like in most voxel games
+        let selected = Some(inventory.selected + INVENTORY_SLOTS - HOTBAR_SLOTS);
+        let slots: Vec<Slot> = inventory.slots[HOTBAR_SLOTS..].iter().chain(inventory.slots[..HOTBAR_SLOTS].iter()).cloned().collect();
+        slot_grid(ui, "inventory", &slots, SLOTS_PER_ROW, selected, |i, right_click| {
+            let index = (i + HOTBAR_SLOTS) % slots.len();
+            clicks.push(SlotClick::Player(index, right_click));
+        });
+    });
+
+    if let (Some(held), Some(pointer)) = (&inventory.held, ctx.pointer_hover_pos()) {
+        egui::Area::new("held").fixed_pos(pointer + egui::vec2(12.0, 12.0)).interactable(false).show(ctx, |ui| {
+            ui.label(format!("{} {}", held.count, block_registry().get(held.id).name));
+        });
+    }
+
+    let mut held = inventory.held.take();
+    for click in clicks {
+        match click {
+            SlotClick::Player(index, right_click) => click_slot(&mut inventory.slots[index], &mut held, right_click, true),
+            SlotClick::Crafting(index, right_click) => click_slot(&mut inventory.crafting[index], &mut held, right_click, true),
+            SlotClick::CraftingResult => {
+                inventory.held = held.take();
+                inventory.craft();
+                held = inventory.held.take();
+            }
+            // a filter is a copy of the held item, nothing is taken
+            SlotClick::Filter(face) => {
+                if let Some(world_position) = inventory.container {
+                    world.read().unwrap().with_block_entity(world_position, |block_entity| {
+                        if let BlockEntity::ItemPipe(pipe) = block_entity {
+                            pipe.filters[face] = held.as_ref().map(|stack| stack.id);
+                        }
+                    });
+                }
+            }
+            SlotClick::Container(index, right_click) => {
+                if let Some(world_position) = inventory.container {
+                    world.read().unwrap().with_block_entity(world_position, |block_entity| {
+                        let accepts = held.as_ref().map_or(true, |stack| block_entity.accepts(index, stack));
+                        click_slot(&mut block_entity.slots_mut()[index], &mut held, right_click, accepts);
+                    });
+                }
+            }
+        }
+    }
+    inventory.held = held;
+}
@@ -1,3 +1,11 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use bevy::{asset::FileAssetIo, utils::HashMap};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use crate::{block_entities::BlockEntityKind, energy::EnergyRole, positions::WorldPosition};
+
 pub const FACES: [[i8; 3]; 6] = [
     [-1, 0, 0], // left
     [0, -1, 0], // bottom
@@ -7,90 +15,446 @@ pub const FACES: [[i8; 3]; 6] = [
     [0, 0, 1],  // front
 ];
 
-pub const ITEMS: [Item; 8] = [
-    Item {
-        name: "Air",
-        id: 0,
-        is_transparent: true,
-        is_solid: false,
-        textures: [0, 0, 0, 0, 0, 0],
-        light_multiplier: 1.0,
-    },
-    Item {
-        name: "Dirt",
-        id: 1,
-        is_transparent: false,
-        is_solid: true,
-        textures: [1, 1, 1, 1, 1, 1],
-        light_multiplier: 0.0,
-    },
-    Item {
-        name: "Grass",
-        id: 2,
-        is_transparent: false,
-        is_solid: true,
-        textures: [2, 1, 2, 2, 7, 2],
-        light_multiplier: 0.0,
-    },
-    Item {
-        name: "Stone",
-        id: 3,
-        is_transparent: false,
-        is_solid: true,
-        textures: [0, 0, 0, 0, 0, 0],
-        light_multiplier: 0.0,
-    },
-    Item {
-        name: "Glass",
-        id: 4,
-        is_transparent: true,
-        is_solid: true,
-        textures: [3, 3, 3, 3, 3, 3],
-        light_multiplier: 0.9,
-    },
-    Item {
-        name: "Wood",
-        id: 5,
-        is_transparent: false,
-        is_solid: true,
-        textures: [5, 6, 5, 5, 6, 5],
-        light_multiplier: 0.0,
-    },
-    Item {
-        name: "Leave",
-        id: 6,
-        is_transparent: true,
-        is_solid: true,
-        textures: [16, 16, 16, 16, 16, 16],
-        light_multiplier: 0.7,
-    },
-    Item {
-        name: "Sand",
-        id: 7,
-        is_transparent: false,
-        is_solid: true,
-        textures: [10, 10, 10, 10, 10, 10],
-        light_multiplier: 0.0,
-    },
-];
+// the neighbour of a cube on the side of FACES[face]
+pub fn offset(world_position: WorldPosition, face: usize) -> WorldPosition {
+    WorldPosition {
+        x: world_position.x + FACES[face][0] as i32,
+        y: world_position.y + FACES[face][1] as i32,
+        z: world_position.z + FACES[face][2] as i32,
+    }
+}
+
+pub const AIR: u16 = 0; // the registry always starts with air, chunks are created full of it
+const BLOCK_REGISTRY_PATH: &str = "blocks.ron"; // relative to the assets folder
 
-#[allow(dead_code)]
-pub enum Items {
-    Air,
-    Dirt,
-    Grass,
-    Stone,
-    Glass,
-    Wood,
-    Leave,
-    Sand,
+static BLOCK_REGISTRY: OnceCell<Arc<BlockRegistry>> = OnceCell::new();
+
+// loaded on first use because meshing and lighting threads need it before any bevy system runs
+pub fn block_registry() -> &'static Arc<BlockRegistry> {
+    BLOCK_REGISTRY.get_or_init(|| {
+        let path = FileAssetIo::get_base_path().join("assets").join(BLOCK_REGISTRY_PATH);
+        match BlockRegistry::load(&path) {
+            Ok(block_registry) => Arc::new(block_registry),
+            Err(error) => panic!("Cannot load the block registry {}: {}", path.display(), error),
+        }
+    })
 }
 
 pub struct Item {
-    pub name: &'static str,
+    pub name: String,
     pub id: u16,
     pub is_transparent: bool,
+    pub is_translucent: bool, // drawn with alpha blending after the opaque cubes, implies is_transparent
     pub is_solid: bool,
-    pub textures: [u16; 6],
+    pub textures: [u16; 6], // texture array layer of each face in the order of FACES, see BlockRegistry::texture_names
+    pub lit_textures: Option<[u16; 6]>, // replace textures while the Lit state is on
     pub light_multiplier: f32,
+    pub emission: [u8; 3], // red, green and blue items light of the cube itself
+    pub hardness: f32,     // negative for cubes that cannot be broken
+    pub fluid: Option<Fluid>,
+    pub state: StateSchema,
+    pub default_state: u8, // state of the cube when it is set without one
+    pub block_entity: Option<BlockEntityKind>,
+    pub burn_time: u32,           // ticks a furnace burns with one of this item, 0 if it is not a fuel
+    pub smelts_into: Option<u16>, // what a furnace makes of this item
+    pub random_tick: Option<RandomTick>,
+    pub drops: Vec<(u16, u32)>, // item id and count given when the cube is broken
+    pub stack_size: u32,        // most items a stack of this item holds
+    pub energy: Option<EnergyRole>,
+    pub fluid_burn_time: u32, // ticks a fluid generator burns with a bucket of this fluid, 0 if it does not burn
+}
+
+impl Item {
+    // texture layer of each face and whether its u and v are swapped, a rotated log keeps its grain along its axis
+    pub fn face_textures(&self, state: u8) -> [(u16, bool); 6] {
+        let textures = match self.lit_textures {
+            Some(lit_textures) if self.state.get(state, StateProperty::Lit) == 1 => lit_textures,
+            _ => self.textures,
+        };
+        let mut textures = textures.map(|layer| (layer, false));
+        match self.state.get(state, StateProperty::Axis) {
+            1 => {
+                textures = rotate_faces(textures, |[x, y, z]| [-y, x, z]);
+                for face in [2, 5] {
+                    textures[face].1 = true;
+                }
+            }
+            2 => {
+                textures = rotate_faces(textures, |[x, y, z]| [x, z, -y]);
+                for face in [0, 1, 3, 4] {
+                    textures[face].1 = true;
+                }
+            }
+            _ => {}
+        }
+        for _ in 0..self.state.get(state, StateProperty::Facing) {
+            textures = rotate_faces(textures, |[x, y, z]| [-z, y, x]);
+        }
+        textures
+    }
+
+    // a block with the Lit property only emits light while it is lit
+    pub fn emission_in_state(&self, state: u8) -> [u8; 3] {
+        if self.state.has(StateProperty::Lit) && self.state.get(state, StateProperty::Lit) == 0 {
+            return [0; 3];
+        }
+        self.emission
+    }
+}
+
+// inverse_rotation gives the direction a face had before the rotation
+fn rotate_faces<T: Copy>(faces: [T; 6], inverse_rotation: impl Fn([i8; 3]) -> [i8; 3]) -> [T; 6] {
+    let mut rotated = faces;
+    for (face, direction) in FACES.iter().enumerate() {
+        let source = inverse_rotation(*direction);
+        rotated[face] = faces[FACES.iter().position(|direction| *direction == source).unwrap()];
+    }
+    rotated
+}
+
+// what the state byte of a cube means for a block, properties are packed from the lowest bit in the order of the registry
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StateProperty {
+    Axis,   // 0 up, 1 along x, 2 along z: where the top and bottom textures go
+    Facing, // 0 front, 1 right, 2 back, 3 left: where the front texture goes, in the order of a quarter turn around y
+    Lit,    // 1 when switched on
+    Level,  // the whole state of a fluid, see fluids
+}
+
+// index in FACES of the side a Facing value turns the front texture to
+pub fn front_face(facing: u8) -> usize {
+    [5, 3, 2, 0][facing as usize % 4]
+}
+
+pub fn opposite_face(face: usize) -> usize {
+    (face + 3) % 6
+}
+
+impl StateProperty {
+    fn nb_bits(&self) -> u32 {
+        match self {
+            StateProperty::Axis | StateProperty::Facing => 2,
+            StateProperty::Lit => 1,
+            StateProperty::Level => 4,
+        }
+    }
+
+    fn nb_values(&self) -> u8 {
+        match self {
+            StateProperty::Axis => 3,
+            StateProperty::Facing => 4,
+            StateProperty::Lit => 2,
+            StateProperty::Level => 9, // up to fluids::FALLING
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StateSchema {
+    properties: Vec<(StateProperty, u32)>, // property and its lowest bit
+}
+
+impl StateSchema {
+    fn new(name: &str, properties: &[StateProperty], is_fluid: bool) -> Result<Self, String> {
+        if is_fluid && !properties.is_empty() {
+            return Err(format!("fluid \"{}\" cannot have a state, it is its level", name));
+        }
+        if properties.contains(&StateProperty::Level) {
+            return Err(format!("block \"{}\" is not a fluid but has a Level", name));
+        }
+        let properties = if is_fluid { &[StateProperty::Level][..] } else { properties };
+        let mut schema = Self::default();
+        let mut shift = 0;
+        for property in properties {
+            if schema.has(*property) {
+                return Err(format!("block \"{}\" has the state property {:?} twice", name, property));
+            }
+            schema.properties.push((*property, shift));
+            shift += property.nb_bits();
+        }
+        if shift > 8 {
+            return Err(format!("the state of block \"{}\" does not fit in a byte", name));
+        }
+        Ok(schema)
+    }
+
+    pub fn has(&self, property: StateProperty) -> bool {
+        self.properties.iter().any(|(other, _)| *other == property)
+    }
+
+    // 0 for a property the block does not have
+    pub fn get(&self, state: u8, property: StateProperty) -> u8 {
+        match self.properties.iter().find(|(other, _)| *other == property) {
+            Some((_, shift)) => (state >> shift) & ((1 << property.nb_bits()) - 1),
+            None => 0,
+        }
+    }
+
+    // the state is left as is for a property the block does not have
+    pub fn with(&self, state: u8, property: StateProperty, value: u8) -> u8 {
+        match self.properties.iter().find(|(other, _)| *other == property) {
+            Some((_, shift)) => {
+                let mask = ((1u16 << property.nb_bits()) - 1) as u8;
+                (state & !(mask << shift)) | ((value & mask) << shift)
+            }
+            None => state,
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    items: Vec<Item>,
+    ids: HashMap<String, u16>,
+    texture_names: Vec<String>, // one per texture array layer
+}
+
+impl BlockRegistry {
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let file: BlockRegistryFile = ron::from_str(&text).map_err(|error| error.to_string())?;
+        Self::from_definitions(file.blocks)
+    }
+
+    // ids are given in file order, they are what region files store so blocks must only be appended
+    fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self, String> {
+        if definitions.first().map_or(true, |definition| definition.name != "air") {
+            return Err("the first block must be \"air\"".to_string());
+        }
+        if definitions.len() > u16::MAX as usize {
+            return Err(format!("too many blocks ({})", definitions.len()));
+        }
+        let mut ids = HashMap::new();
+        for (id, definition) in definitions.iter().enumerate() {
+            if ids.insert(definition.name.clone(), id as u16).is_some() {
+                return Err(format!("block \"{}\" is defined twice", definition.name));
+            }
+        }
+
+        let mut texture_names: Vec<String> = Vec::new();
+        let mut items = Vec::with_capacity(definitions.len());
+        for (id, definition) in definitions.into_iter().enumerate() {
+            let textures = match &definition.textures {
+                Some(textures) => texture_layers(textures, &mut texture_names),
+                None if id as u16 == AIR => [0; 6],
+                None => return Err(format!("block \"{}\" has no textures", definition.name)),
+            };
+            let lit_textures = definition.lit_textures.as_ref().map(|textures| texture_layers(textures, &mut texture_names));
+            if let Some(fluid) = &definition.fluid {
+                if fluid.flow_distance > 7 || fluid.tick_delay == 0 {
+                    return Err(format!("fluid \"{}\" needs a flow_distance up to 7 and a tick_delay of at least 1", definition.name));
+                }
+            }
+            if definition.energy.map_or(false, |role| role != EnergyRole::Cable && role.capacity() == 0) {
+                return Err(format!("block \"{}\" needs an energy capacity", definition.name));
+            }
+            if definition.stack_size == 0 {
+                return Err(format!("block \"{}\" has a stack_size of 0", definition.name));
+            }
+            let state = StateSchema::new(&definition.name, &definition.state, definition.fluid.is_some())?;
+            if lit_textures.is_some() && !state.has(StateProperty::Lit) {
+                return Err(format!("block \"{}\" has lit_textures but no Lit state", definition.name));
+            }
+            let mut default_state = 0;
+            for (property, value) in definition.default_state.iter() {
+                if !state.has(*property) || *value >= property.nb_values() {
+                    return Err(format!("block \"{}\" has an invalid default state {:?}: {}", definition.name, property, value));
+                }
+                default_state = state.with(default_state, *property, *value);
+            }
+            let smelts_into = match &definition.smelts_into {
+                Some(name) => match ids.get(name) {
+                    Some(smelted_id) => Some(*smelted_id),
+                    None => return Err(format!("block \"{}\" smelts into unknown block \"{}\"", definition.name, name)),
+                },
+                None => None,
+            };
+            let random_tick = match &definition.random_tick {
+                Some(RandomTickDefinition::Spread { onto }) => match ids.get(onto) {
+                    Some(onto) => Some(RandomTick::Spread { onto: *onto }),
+                    None => return Err(format!("block \"{}\" spreads onto unknown block \"{}\"", definition.name, onto)),
+                },
+                None => None,
+            };
+            let drops = match definition.drops {
+                Some(drops) => {
+                    let mut resolved = Vec::with_capacity(drops.len());
+                    for (name, count) in drops {
+                        match ids.get(&name) {
+                            Some(drop_id) => resolved.push((*drop_id, count)),
+                            None => return Err(format!("block \"{}\" drops unknown block \"{}\"", definition.name, name)),
+                        }
+                    }
+                    resolved
+                }
+                None if id as u16 == AIR => Vec::new(),
+                None => vec![(id as u16, 1)],
+            };
+            items.push(Item {
+                name: definition.name,
+                id: id as u16,
+                is_transparent: definition.is_transparent || definition.is_translucent,
+                is_translucent: definition.is_translucent,
+                is_solid: definition.is_solid,
+                textures,
+                lit_textures,
+                light_multiplier: definition.light_multiplier.unwrap_or(if definition.is_transparent || definition.is_translucent { 1.0 } else { 0.0 }),
+                emission: definition.emission,
+                hardness: definition.hardness,
+                fluid: definition.fluid,
+                state,
+                default_state,
+                block_entity: definition.block_entity,
+                burn_time: definition.burn_time,
+                smelts_into,
+                random_tick,
+                drops,
+                stack_size: definition.stack_size,
+                energy: definition.energy,
+                fluid_burn_time: definition.fluid_burn_time,
+            });
+        }
+        if texture_names.is_empty() {
+            return Err("no block has a texture".to_string());
+        }
+        Ok(Self { items, ids, texture_names })
+    }
+
+    #[inline]
+    pub fn get(&self, id: u16) -> &Item {
+        &self.items[id as usize]
+    }
+
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn is_fluid(&self, id: u16) -> bool {
+        self.get(id).fluid.is_some()
+    }
+
+    // cubes a placed block can take the place of
+    pub fn is_replaceable(&self, id: u16) -> bool {
+        id == AIR || self.is_fluid(id)
+    }
+
+    // for blocks the code itself relies on, a missing one is a broken registry file
+    pub fn expect_id(&self, name: &str) -> u16 {
+        self.id(name).unwrap_or_else(|| panic!("Block \"{}\" is missing from {}", name, BLOCK_REGISTRY_PATH))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn texture_names(&self) -> &[String] {
+        &self.texture_names
+    }
+}
+
+// layers are given in order of first use so every referenced texture is loaded exactly once
+fn texture_layers(textures: &FaceTextures, texture_names: &mut Vec<String>) -> [u16; 6] {
+    textures.faces().map(|name| match texture_names.iter().position(|texture_name| texture_name == name) {
+        Some(layer) => layer as u16,
+        None => {
+            texture_names.push(name.clone());
+            texture_names.len() as u16 - 1
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct BlockRegistryFile {
+    blocks: Vec<BlockDefinition>,
+}
+
+#[derive(Deserialize)]
+struct BlockDefinition {
+    name: String,
+    #[serde(default)]
+    textures: Option<FaceTextures>, // only air can omit them, it is never drawn
+    #[serde(default)]
+    lit_textures: Option<FaceTextures>,
+    #[serde(default)]
+    is_transparent: bool,
+    #[serde(default)]
+    is_translucent: bool,
+    #[serde(default = "default_is_solid")]
+    is_solid: bool,
+    #[serde(default)]
+    light_multiplier: Option<f32>, // defaults to 1 for transparent blocks and 0 for opaque ones
+    #[serde(default)]
+    emission: [u8; 3],
+    #[serde(default = "default_hardness")]
+    hardness: f32,
+    #[serde(default)]
+    drops: Option<Vec<(String, u32)>>, // the block drops itself when omitted
+    #[serde(default)]
+    fluid: Option<Fluid>,
+    #[serde(default)]
+    state: Vec<StateProperty>,
+    #[serde(default)]
+    default_state: Vec<(StateProperty, u8)>, // properties left out are 0
+    #[serde(default)]
+    block_entity: Option<BlockEntityKind>,
+    #[serde(default)]
+    burn_time: u32,
+    #[serde(default)]
+    smelts_into: Option<String>,
+    #[serde(default)]
+    random_tick: Option<RandomTickDefinition>,
+    #[serde(default = "default_stack_size")]
+    stack_size: u32,
+    #[serde(default)]
+    energy: Option<EnergyRole>,
+    #[serde(default)]
+    fluid_burn_time: u32,
+}
+
+// what a block does when a random tick picks it, see ticks
+#[derive(Clone, Copy)]
+pub enum RandomTick {
+    Spread { onto: u16 }, // like grass on dirt
+}
+
+#[derive(Deserialize)]
+enum RandomTickDefinition {
+    Spread { onto: String },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct Fluid {
+    pub flow_distance: u8, // how many cubes a flow goes sideways from its source, at most 7
+    pub tick_delay: u64,   // world ticks between two steps of the flow
+    #[serde(default)]
+    pub renews: bool, // a flowing cube between two sources becomes a source
+}
+
+fn default_is_solid() -> bool {
+    true
+}
+
+fn default_hardness() -> f32 {
+    1.0
+}
+
+fn default_stack_size() -> u32 {
+    64
+}
+
+// texture names are file names without extension in assets/Textures/Blocks
+#[derive(Deserialize)]
+enum FaceTextures {
+    All(String),
+    Column { top: String, bottom: String, side: String },
+    Faces { left: String, bottom: String, back: String, right: String, top: String, front: String },
+}
+
+impl FaceTextures {
+    fn faces(&self) -> [&String; 6] {
+        match self {
+            FaceTextures::All(texture) => [texture; 6],
+            FaceTextures::Column { top, bottom, side } => [side, bottom, side, side, top, side],
+            FaceTextures::Faces { left, bottom, back, right, top, front } => [left, bottom, back, right, top, front],
+        }
+    }
 }
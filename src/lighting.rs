@@ -1,12 +1,18 @@
-use std::{cmp::max, sync::{RwLockWriteGuard, RwLockReadGuard, RwLock, Arc}};
+use std::{
+    cmp::max,
+    collections::VecDeque,
+    sync::{Arc, RwLock, RwLockReadGuard},
+};
 
 use bevy::utils::HashMap;
 use block_mesh::ndshape::ConstShape;
 
 use crate::{
-    chunk::{Chunk, ChunkShape, Cube, CHUNK_SIZE, REAL_CHUNK_SIZE},
-    items::ITEMS,
+    chunk::{Chunk, ChunkShape, CHUNK_SIZE, REAL_CHUNK_SIZE},
+    chunk_storage::{ChunkStorage, CHUNK_VOLUME},
+    items::{block_registry, FACES},
     positions::{world_position_to_chunk_position, world_position_to_position_in_chunk, ChunkPosition, WorldPosition},
+    world::World,
 };
 
 pub const MIN_LIGHT_LEVEL: u8 = 5;
@@ -15,9 +21,42 @@ const LIGHT_ATTENUATION_POWER: u8 = 2;
 const PROFOUND_CHUNK: i32 = -(256.0 / LIGHT_ATTENUATION_POWER as f64 / (REAL_CHUNK_SIZE as f64)) as i32 + LIGHT_ATTENUATION_CHUNK_START_LEVEL;
 pub const DIFFUSE_ATTENUATION: u8 = 10;
 
-pub struct NaturalLightModification {
+// natural light comes from the sky, items light from emissive cubes in three colour channels, all are propagated the same way
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    Natural,
+    Red,
+    Green,
+    Blue,
+}
+
+pub const ITEMS_LIGHT_CHANNELS: [LightChannel; 3] = [LightChannel::Red, LightChannel::Green, LightChannel::Blue];
+
+impl LightChannel {
+    pub fn min_level(&self) -> u8 {
+        match self {
+            LightChannel::Natural => MIN_LIGHT_LEVEL,
+            _ => 0,
+        }
+    }
+
+    // emission of a cube in this channel
+    pub fn emission(&self, id: u16, state: u8) -> u8 {
+        let emission = block_registry().get(id).emission_in_state(state);
+        match self {
+            LightChannel::Natural => 0,
+            LightChannel::Red => emission[0],
+            LightChannel::Green => emission[1],
+            LightChannel::Blue => emission[2],
+        }
+    }
+}
+
+pub struct LightModification {
+    pub channel: LightChannel,
     pub light_level: u8,
     pub position: usize,
+    pub removal: bool, // the cube was darkened to light_level, what it lit must be removed
 }
 
 pub fn recalculate_natural_light(current_chunk: &Chunk) {
@@ -32,140 +71,269 @@ pub fn recalculate_natural_light(current_chunk: &Chunk) {
 
 pub fn recalculate_diffuse_light(current_chunk: &Chunk) {
     let mut cubes_lock = current_chunk.cubes.write().unwrap();
-    let mut other_chunks_natural_lock = current_chunk.other_chunks_natural_light_modifications.write().unwrap();
-    for x in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_SIZE {
-            for z in 0..CHUNK_SIZE {
-                let linearized = ChunkShape::linearize([x, y, z]);
-                if cubes_lock[linearized as usize].natural_light_level as u16 > MIN_LIGHT_LEVEL as u16 + DIFFUSE_ATTENUATION as u16 {
-                    let light_level = cubes_lock[linearized as usize].natural_light_level;
-                    diffuse_light_from_pos(current_chunk, &mut cubes_lock, x, y, z, light_level, true, &mut other_chunks_natural_lock);
+    let mut other_chunks_light_lock = current_chunk.other_chunks_light_modifications.write().unwrap();
+    let mut queue = VecDeque::new();
+    for i in 0..CHUNK_VOLUME {
+        if cubes_lock.get_natural_light(i) as u16 > MIN_LIGHT_LEVEL as u16 + DIFFUSE_ATTENUATION as u16 {
+            queue.push_back(i);
+        }
+    }
+    propagate_light(current_chunk, &mut cubes_lock, LightChannel::Natural, queue, &mut other_chunks_light_lock);
+}
+
+pub fn recalculate_items_light(current_chunk: &Chunk) {
+    let mut cubes_lock = current_chunk.cubes.write().unwrap();
+    let mut other_chunks_light_lock = current_chunk.other_chunks_light_modifications.write().unwrap();
+    if cubes_lock.palette().iter().all(|id| block_registry().get(*id).emission == [0; 3]) {
+        return;
+    }
+    for channel in ITEMS_LIGHT_CHANNELS {
+        let mut queue = VecDeque::new();
+        for i in 0..CHUNK_VOLUME {
+            let emission = channel.emission(cubes_lock.get_id(i), cubes_lock.get_state(i));
+            if emission > cubes_lock.get_light(channel, i) {
+                cubes_lock.set_light(channel, i, emission);
+                queue.push_back(i);
+            }
+        }
+        propagate_light(current_chunk, &mut cubes_lock, channel, queue, &mut other_chunks_light_lock);
+    }
+}
+
+fn neighbour_index(position: [u32; 3], face: &[i8; 3]) -> Option<usize> {
+    let mut neighbour = [0; 3];
+    for axis in 0..3 {
+        let value = position[axis] as i32 + face[axis] as i32;
+        if value < 0 || value >= CHUNK_SIZE as i32 {
+            return None;
+        }
+        neighbour[axis] = value as u32;
+    }
+    Some(ChunkShape::linearize(neighbour) as usize)
+}
+
+// a real cube on the border of the chunk is mirrored in the padding of the chunk next to it
+fn push_border_modifications(current_chunk: &Chunk, position: [u32; 3], channel: LightChannel, light_level: u8, removal: bool, other_chunks_light_lock: &mut Vec<(LightModification, ChunkPosition)>) {
+    if position.iter().any(|axis| *axis == 0 || *axis == CHUNK_SIZE - 1) {
+        return;
+    }
+    for axis in 0..3 {
+        let (offset, mirrored) = if position[axis] == 1 {
+            (-1, CHUNK_SIZE - 1)
+        } else if position[axis] == CHUNK_SIZE - 2 {
+            (1, 0)
+        } else {
+            continue;
+        };
+        let mut other_position = position;
+        other_position[axis] = mirrored;
+        let mut chunk_position = [current_chunk.position.x, current_chunk.position.y, current_chunk.position.z];
+        chunk_position[axis] += offset;
+        other_chunks_light_lock.push((
+            LightModification {
+                channel,
+                light_level,
+                position: ChunkShape::linearize(other_position) as usize,
+                removal,
+            },
+            ChunkPosition {
+                x: chunk_position[0],
+                y: chunk_position[1],
+                z: chunk_position[2],
+            },
+        ));
+    }
+}
+
+// breadth first flood fill from cubes whose light is already set
+pub fn propagate_light(current_chunk: &Chunk, cubes_lock: &mut ChunkStorage, channel: LightChannel, mut queue: VecDeque<usize>, other_chunks_light_lock: &mut Vec<(LightModification, ChunkPosition)>) {
+    let min_level = channel.min_level();
+    while let Some(i) = queue.pop_front() {
+        let light_level = cubes_lock.get_light(channel, i);
+        let position = ChunkShape::delinearize(i as u32);
+        push_border_modifications(current_chunk, position, channel, light_level, false, other_chunks_light_lock);
+        if light_level <= min_level + DIFFUSE_ATTENUATION {
+            continue;
+        }
+        for face in FACES.iter() {
+            let neighbour = match neighbour_index(position, face) {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+            let item = block_registry().get(cubes_lock.get_id(neighbour));
+            if !item.is_transparent {
+                continue;
+            }
+            let neighbour_light = ((light_level - DIFFUSE_ATTENUATION) as f32 * item.light_multiplier) as u8;
+            if neighbour_light > min_level && neighbour_light > cubes_lock.get_light(channel, neighbour) {
+                cubes_lock.set_light(channel, neighbour, neighbour_light);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+}
+
+// the queue holds cubes already darkened with the light they had before, returns the lit cubes that must be propagated again
+pub fn remove_light(current_chunk: &Chunk, cubes_lock: &mut ChunkStorage, channel: LightChannel, mut removal_queue: VecDeque<(usize, u8)>, other_chunks_light_lock: &mut Vec<(LightModification, ChunkPosition)>) -> VecDeque<usize> {
+    let min_level = channel.min_level();
+    for (i, _) in removal_queue.iter() {
+        push_border_modifications(current_chunk, ChunkShape::delinearize(*i as u32), channel, cubes_lock.get_light(channel, *i), true, other_chunks_light_lock);
+    }
+    let mut refill_queue = VecDeque::new();
+    while let Some((i, light_level)) = removal_queue.pop_front() {
+        let position = ChunkShape::delinearize(i as u32);
+        for face in FACES.iter() {
+            let neighbour = match neighbour_index(position, face) {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+            let neighbour_light = cubes_lock.get_light(channel, neighbour);
+            if neighbour_light <= min_level {
+                continue;
+            }
+            // an emissive cube keeps its own light whatever lit it before
+            let emission = channel.emission(cubes_lock.get_id(neighbour), cubes_lock.get_state(neighbour));
+            if neighbour_light < light_level && neighbour_light > emission {
+                cubes_lock.set_light(channel, neighbour, max(min_level, emission));
+                push_border_modifications(current_chunk, ChunkShape::delinearize(neighbour as u32), channel, max(min_level, emission), true, other_chunks_light_lock);
+                removal_queue.push_back((neighbour, neighbour_light));
+                if emission > 0 {
+                    refill_queue.push_back(neighbour);
+                }
+            } else {
+                refill_queue.push_back(neighbour);
+            }
+        }
+    }
+    refill_queue
+}
+
+pub fn apply_light_modifications(current_chunk: &Chunk, modifications: Vec<LightModification>) {
+    let mut cubes_lock = current_chunk.cubes.write().unwrap();
+    let mut other_chunks_light_lock = current_chunk.other_chunks_light_modifications.write().unwrap();
+
+    for channel in [LightChannel::Natural, LightChannel::Red, LightChannel::Green, LightChannel::Blue] {
+        let mut removal_queue = VecDeque::new();
+        for modification in modifications.iter().filter(|modification| modification.channel == channel && modification.removal) {
+            let light_level = cubes_lock.get_light(channel, modification.position);
+            if light_level > modification.light_level {
+                cubes_lock.set_light(channel, modification.position, modification.light_level);
+                removal_queue.push_back((modification.position, light_level));
+            }
+        }
+        let mut queue = remove_light(current_chunk, &mut cubes_lock, channel, removal_queue, &mut other_chunks_light_lock);
+        for modification in modifications.iter().filter(|modification| modification.channel == channel && !modification.removal) {
+            if cubes_lock.get_light(channel, modification.position) < modification.light_level {
+                cubes_lock.set_light(channel, modification.position, modification.light_level);
+                queue.push_back(modification.position);
+            }
+        }
+        propagate_light(current_chunk, &mut cubes_lock, channel, queue, &mut other_chunks_light_lock);
+    }
+}
+
+// called when a single cube changed, walks down the sky column of the cube through every loaded chunk it lights
+pub fn relight_cube(world: &World, world_position: WorldPosition) {
+    let owner_position = world_position_to_chunk_position(world_position);
+    let [x, y, z] = world_position_to_position_in_chunk(world_position);
+    let mut chunk_position = owner_position;
+
+    loop {
+        let chunk = match world.chunks.read().unwrap().get(&chunk_position) {
+            Some(chunk) => chunk.clone(),
+            None => break,
+        };
+        let chunk_lock = chunk.read().unwrap();
+        if !*chunk_lock.filled.read().unwrap() {
+            break;
+        }
+        let top = if chunk_position == owner_position { y } else { CHUNK_SIZE - 1 };
+
+        let mut column_was_lit = false;
+        let refill_queue = {
+            let mut cubes_lock = chunk_lock.cubes.write().unwrap();
+            let mut other_chunks_light_lock = chunk_lock.other_chunks_light_modifications.write().unwrap();
+            let mut removal_queue = VecDeque::new();
+            for column_y in 0..=top {
+                let i = ChunkShape::linearize([x, column_y, z]) as usize;
+                let light_level = cubes_lock.get_natural_light(i);
+                if light_level > MIN_LIGHT_LEVEL {
+                    column_was_lit = true;
+                    cubes_lock.set_natural_light(i, MIN_LIGHT_LEVEL);
+                    removal_queue.push_back((i, light_level));
+                }
+            }
+            remove_light(&chunk_lock, &mut cubes_lock, LightChannel::Natural, removal_queue, &mut other_chunks_light_lock)
+        };
+
+        cast_natural_light(&chunk_lock, x, z, false, &world.chunks.read().unwrap());
+
+        let mut column_is_lit = false;
+        {
+            let mut cubes_lock = chunk_lock.cubes.write().unwrap();
+            let mut other_chunks_light_lock = chunk_lock.other_chunks_light_modifications.write().unwrap();
+            let mut queue = refill_queue;
+            for column_y in 0..CHUNK_SIZE {
+                let i = ChunkShape::linearize([x, column_y, z]) as usize;
+                if cubes_lock.get_natural_light(i) > MIN_LIGHT_LEVEL {
+                    column_is_lit = true;
+                    queue.push_back(i);
+                }
+            }
+            if chunk_position == owner_position {
+                // a removed cube lets the light of its neighbours in
+                for face in FACES.iter() {
+                    if let Some(neighbour) = neighbour_index([x, y, z], face) {
+                        queue.push_back(neighbour);
+                    }
                 }
             }
+            propagate_light(&chunk_lock, &mut cubes_lock, LightChannel::Natural, queue, &mut other_chunks_light_lock);
+
+            if chunk_position == owner_position {
+                relight_items_cube(&chunk_lock, &mut cubes_lock, [x, y, z], &mut other_chunks_light_lock);
+            }
         }
+        chunk_lock.modify_other_chunks();
+        if chunk_lock.drawn {
+            world.chunks_to_update.write().unwrap().insert_if_absent(chunk_position);
+        }
+
+        if !column_was_lit && !column_is_lit {
+            break;
+        }
+        chunk_position.y -= 1;
     }
 }
 
-pub fn diffuse_light_from_pos(
-    current_chunk: &Chunk,
-    cubes_lock: &mut RwLockWriteGuard<[Cube; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize]>,
-    x: u32,
-    y: u32,
-    z: u32,
-    mut parent_light: u8,
-    first: bool,
-    other_chunks_natural_lock: &mut RwLockWriteGuard<Vec<(NaturalLightModification, ChunkPosition)>>,
-) {
-    let linearized = ChunkShape::linearize([x, y, z]);
-    parent_light = (parent_light as f32 * ITEMS[cubes_lock[linearized as usize].id as usize].light_multiplier) as u8;
-    if (cubes_lock[linearized as usize].natural_light_level < parent_light || first) && parent_light > MIN_LIGHT_LEVEL && ITEMS[cubes_lock[linearized as usize].id as usize].is_transparent {
-        cubes_lock[linearized as usize].natural_light_level = parent_light;
-        if DIFFUSE_ATTENUATION > parent_light {
-            return;
-        }
-        if x > 0 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x - 1, y, z, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if x == 1 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([CHUNK_SIZE - 1, y, z]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x - 1,
-                    y: current_chunk.position.y,
-                    z: current_chunk.position.z,
-                },
-            ));
-        }
-        if x < CHUNK_SIZE - 1 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x + 1, y, z, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if x == CHUNK_SIZE - 2 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([0, y, z]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x + 1,
-                    y: current_chunk.position.y,
-                    z: current_chunk.position.z,
-                },
-            ));
-        }
-        if y > 0 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x, y - 1, z, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if y == 1 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([x, CHUNK_SIZE - 1, z]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x,
-                    y: current_chunk.position.y - 1,
-                    z: current_chunk.position.z,
-                },
-            ));
-        }
-        if y < CHUNK_SIZE - 1 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x, y + 1, z, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if y == CHUNK_SIZE - 2 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([x, 0, z]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x,
-                    y: current_chunk.position.y + 1,
-                    z: current_chunk.position.z,
-                },
-            ));
-        }
-        if z > 0 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x, y, z - 1, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if z == 1 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([x, y, CHUNK_SIZE - 1]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x,
-                    y: current_chunk.position.y,
-                    z: current_chunk.position.z - 1,
-                },
-            ));
-        }
-        if z < CHUNK_SIZE - 1 {
-            diffuse_light_from_pos(current_chunk, cubes_lock, x, y, z + 1, parent_light - DIFFUSE_ATTENUATION, false, other_chunks_natural_lock);
-        }
-        if z == CHUNK_SIZE - 2 {
-            other_chunks_natural_lock.push((
-                NaturalLightModification {
-                    light_level: parent_light - DIFFUSE_ATTENUATION,
-                    position: ChunkShape::linearize([x, y, 0]) as usize,
-                },
-                ChunkPosition {
-                    x: current_chunk.position.x,
-                    y: current_chunk.position.y,
-                    z: current_chunk.position.z + 1,
-                },
-            ));
+fn relight_items_cube(current_chunk: &Chunk, cubes_lock: &mut ChunkStorage, position: [u32; 3], other_chunks_light_lock: &mut Vec<(LightModification, ChunkPosition)>) {
+    let i = ChunkShape::linearize(position) as usize;
+    for channel in ITEMS_LIGHT_CHANNELS {
+        let light_level = cubes_lock.get_light(channel, i);
+        let mut removal_queue = VecDeque::new();
+        if light_level > 0 {
+            cubes_lock.set_light(channel, i, 0);
+            removal_queue.push_back((i, light_level));
+        }
+        let mut queue = remove_light(current_chunk, cubes_lock, channel, removal_queue, other_chunks_light_lock);
+        let emission = channel.emission(cubes_lock.get_id(i), cubes_lock.get_state(i));
+        if emission > cubes_lock.get_light(channel, i) {
+            cubes_lock.set_light(channel, i, emission);
+        }
+        queue.push_back(i);
+        for face in FACES.iter() {
+            if let Some(neighbour) = neighbour_index(position, face) {
+                queue.push_back(neighbour);
+            }
         }
+        propagate_light(current_chunk, cubes_lock, channel, queue, other_chunks_light_lock);
     }
 }
 
 fn no_light_column(current_chunk: &Chunk, x: u32, z: u32) {
     let mut cubes_lock = current_chunk.cubes.write().unwrap();
     for y in 1..CHUNK_SIZE - 1 {
-        cubes_lock[ChunkShape::linearize([x, y, z]) as usize].natural_light_level = MIN_LIGHT_LEVEL;
+        cubes_lock.set_natural_light(ChunkShape::linearize([x, y, z]) as usize, MIN_LIGHT_LEVEL);
     }
 }
 
@@ -173,14 +341,14 @@ fn get_light_multiplier_at_world_position(current_chunk: &Chunk, world_position:
     let chunk_position = world_position_to_chunk_position(world_position);
     let position_in_chunk = ChunkShape::linearize(world_position_to_position_in_chunk(world_position)) as usize;
     if current_chunk.position == chunk_position {
-        return ITEMS[current_chunk.cubes.read().unwrap()[position_in_chunk].id as usize].light_multiplier;
+        return block_registry().get(current_chunk.cubes.read().unwrap().get_id(position_in_chunk)).light_multiplier;
     } else if chunks_read_lock.contains_key(&chunk_position) {
         let chunk = chunks_read_lock.get(&chunk_position).unwrap().clone();
         if *chunk.read().unwrap().filled.read().unwrap() {
-            return ITEMS[chunk.read().unwrap().cubes.read().unwrap()[position_in_chunk].id as usize].light_multiplier;
+            return block_registry().get(chunk.read().unwrap().cubes.read().unwrap().get_id(position_in_chunk)).light_multiplier;
         }
     }
-    return ITEMS[current_chunk.chunk_filling.fill_block(world_position, current_chunk, false).id as usize].light_multiplier;
+    return block_registry().get(current_chunk.chunk_filling.fill_block(world_position, current_chunk, false).id).light_multiplier;
 }
 
 fn cast_natural_light(current_chunk: &Chunk, x: u32, z: u32, filling_chunk: bool, chunks_read_lock: &RwLockReadGuard<HashMap<ChunkPosition, Arc<RwLock<Chunk>>>>) {
@@ -203,12 +371,12 @@ fn cast_natural_light(current_chunk: &Chunk, x: u32, z: u32, filling_chunk: bool
         light_level *= get_light_multiplier_at_world_position(current_chunk, WorldPosition { x: gx, y: current_height, z: gz }, chunks_read_lock);
 
         if chunk_position.y == current_chunk.position.y {
-            current_chunk.cubes.write().unwrap()[ChunkShape::linearize([x, ((current_height).rem_euclid(REAL_CHUNK_SIZE as i32)) as u32 + 1, z]) as usize].natural_light_level = max(light_level as u8, MIN_LIGHT_LEVEL);
+            current_chunk.cubes.write().unwrap().set_natural_light(ChunkShape::linearize([x, ((current_height).rem_euclid(REAL_CHUNK_SIZE as i32)) as u32 + 1, z]) as usize, max(light_level as u8, MIN_LIGHT_LEVEL));
         } else if f32::floor((current_height - 1) as f32 / REAL_CHUNK_SIZE as f32) as i32 == current_chunk.position.y {
             if light_level < MIN_LIGHT_LEVEL as f32 {
                 return no_light_column(current_chunk, x, z);
             }
-            current_chunk.cubes.write().unwrap()[ChunkShape::linearize([x, CHUNK_SIZE - 1, z]) as usize].natural_light_level = light_level as u8;
+            current_chunk.cubes.write().unwrap().set_natural_light(ChunkShape::linearize([x, CHUNK_SIZE - 1, z]) as usize, light_level as u8);
         } else if light_level <= MIN_LIGHT_LEVEL as f32 {
             if filling_chunk {
                 return;
@@ -1,11 +1,27 @@
+mod block_entities;
 mod chunk;
 mod chunk_filling;
+mod chunk_storage;
+mod conveyors;
+mod crafting;
+mod day_night;
+mod energy;
+mod fluid_transport;
+mod fluids;
 mod game_material;
 mod greedy_meshing_inits;
+mod inventory;
+mod inventory_ui;
 mod items;
 mod lighting;
+mod pipes;
 mod positions;
+mod raycast;
+mod region;
+mod streaming;
 mod structures;
+mod texture_array;
+mod ticks;
 mod world;
 mod events;
 
@@ -15,41 +31,50 @@ use std::{
 };
 
 use bevy::{
-    asset::LoadState,
+    app::AppExit,
     diagnostic::LogDiagnosticsPlugin,
     input::{keyboard::KeyboardInput, ButtonState},
     pbr::wireframe::{WireframeConfig, WireframePlugin},
     prelude::*,
     render::{
-        render_resource::{AddressMode, SamplerDescriptor},
+        render_resource::{AddressMode, FilterMode, SamplerDescriptor},
         texture::ImageSettings,
     },
+    time::FixedTimestep,
     window::PresentMode,
 };
 use bevy_inspector_egui::WorldInspectorPlugin;
-use events::{handle_keyboard, handle_mouse_motion, GameCamera};
+use conveyors::draw_conveyor_items;
+use day_night::{advance_world_clock, spawn_sky_light, update_sky, WorldClock};
+use events::{handle_keyboard, handle_mouse_buttons, handle_mouse_motion, GameCamera};
 use game_material::GameMaterial;
+use inventory::{give_creative_kit, handle_hotbar_selection, toggle_inventory, PlayerInventory};
+use inventory_ui::draw_inventory;
+use items::block_registry;
+use pipes::draw_pipe_items;
+use raycast::{spawn_block_outline, update_targeted_block, TargetedBlock};
+use streaming::{stream_chunks_around_player, unload_far_chunks, ChunkBudget, ViewDistance};
+use texture_array::{block_textures_load_state, build_texture_array, generate_mipmaps, load_block_textures};
+use ticks::{tick_world, TICKS_PER_SECOND};
 
 const WIDTH: f32 = 1920.0;
 const HEIGHT: f32 = 1080.0;
 const CHUNK_PER_FRAME: usize = 16;
-const TEXTURE_ARRAY_SIZE: u32 = 18;
 
 struct LoadingTexture {
     is_loaded: bool,
-    handle: Handle<Image>,
+    textures: Vec<(String, Handle<Image>)>,
 }
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut wireframe_config: ResMut<WireframeConfig>) {
     wireframe_config.global = false;
 
     let world = Arc::new(RwLock::new(world::World::new()));
-    world.read().unwrap().start_world(world.clone());
     commands.insert_resource(world);
 
     commands.insert_resource(LoadingTexture {
         is_loaded: false,
-        handle: asset_server.load("Textures/BlockAtlas.png"),
+        textures: load_block_textures(&asset_server, block_registry().texture_names()),
     });
     commands.insert_resource(Instant::now());
 
@@ -59,25 +84,54 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut wireframe_c
     }).insert(GameCamera::default());
 }
 
-fn create_material(mut images: ResMut<Assets<Image>>, world: ResMut<Arc<RwLock<world::World>>>, mut materials: ResMut<Assets<GameMaterial>>, mut loading_texture: ResMut<LoadingTexture>, asset_server: Res<AssetServer>) {
-    if loading_texture.is_loaded || asset_server.get_load_state(loading_texture.handle.clone()) != LoadState::Loaded {
+fn create_material(
+    mut images: ResMut<Assets<Image>>,
+    world: ResMut<Arc<RwLock<world::World>>>,
+    mut materials: ResMut<Assets<GameMaterial>>,
+    mut loading_texture: ResMut<LoadingTexture>,
+    asset_server: Res<AssetServer>,
+    clock: Res<WorldClock>,
+) {
+    if loading_texture.is_loaded {
         return;
     }
+    let result = match block_textures_load_state(&asset_server, &loading_texture.textures) {
+        Some(result) => result.and_then(|_| build_texture_array(&images, &loading_texture.textures)),
+        None => return,
+    };
+    let mut image = match result {
+        Ok(image) => image,
+        Err(errors) => panic!("Cannot build the block texture array:\n{}", errors.join("\n")),
+    };
     loading_texture.is_loaded = true;
-    let image = images.get_mut(&loading_texture.handle).unwrap();
+    generate_mipmaps(&mut image);
+    // close cubes keep their pixels, distant ones blend between mip levels instead of shimmering
     image.sampler_descriptor = bevy::render::texture::ImageSampler::Descriptor(SamplerDescriptor {
         address_mode_u: AddressMode::Repeat,
         address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Linear,
+        mipmap_filter: FilterMode::Linear,
         ..Default::default()
     });
-    image.reinterpret_stacked_2d_as_array(TEXTURE_ARRAY_SIZE);
+    // the layers are copied in the array, the single textures are not needed anymore
+    loading_texture.textures.clear();
+    let array_texture = images.add(image);
     let material_handle = materials.add(GameMaterial {
-        array_texture: loading_texture.handle.clone(),
+        array_texture: array_texture.clone(),
+        sky_brightness: clock.sky_brightness(),
+        alpha_mode: AlphaMode::Opaque,
+    });
+    let translucent_material_handle = materials.add(GameMaterial {
+        array_texture,
+        sky_brightness: clock.sky_brightness(),
+        alpha_mode: AlphaMode::Blend,
     });
     *world.read().unwrap().material.write().unwrap() = material_handle;
+    *world.read().unwrap().translucent_material.write().unwrap() = translucent_material_handle;
 }
 
-fn draw_chunks_to_draw(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, world: ResMut<Arc<RwLock<world::World>>>, loading_texture: Res<LoadingTexture>, time: Res<Instant>) {
+fn draw_chunks_to_draw(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, world: ResMut<Arc<RwLock<world::World>>>, loading_texture: Res<LoadingTexture>, time: Res<Instant>, mut first_chunks_generated: Local<bool>) {
     if !loading_texture.is_loaded {
         return;
     }
@@ -86,15 +140,21 @@ fn draw_chunks_to_draw(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>,
         if chunks_to_draw.read().unwrap().len() >= 1 {
             let pos = chunks_to_draw.write().unwrap().pop_front().unwrap();
             let chunks = world.read().unwrap().chunks.clone();
-            let chunk = chunks.read().unwrap().get(&pos).unwrap().clone();
-            chunk.write().unwrap().draw_mesh(&mut commands, &mut meshes, world.read().unwrap().material.read().unwrap().clone());
+            let chunk = match chunks.read().unwrap().get(&pos) {
+                Some(chunk) => chunk.clone(),
+                None => continue,
+            };
+            let world_read_lock = world.read().unwrap();
+            let material = world_read_lock.material.read().unwrap().clone();
+            let translucent_material = world_read_lock.translucent_material.read().unwrap().clone();
+            chunk.write().unwrap().draw_mesh(&mut commands, &mut meshes, material, translucent_material);
         } else {
             break;
         }
     }
-    if *world.read().unwrap().nb_chunks_generating.read().unwrap() == 0 {
+    if !*first_chunks_generated && *world.read().unwrap().nb_chunks_generating.read().unwrap() == 0 && world.read().unwrap().chunks.read().unwrap().len() > 0 {
         println!("All chunks generated in {} ms", time.elapsed().as_millis());
-        *world.read().unwrap().nb_chunks_generating.write().unwrap() = 1;
+        *first_chunks_generated = true;
     }
 }
 
@@ -116,6 +176,12 @@ fn force_update_all_chunks(world: ResMut<Arc<RwLock<world::World>>>, mut keys: E
     }
 }
 
+fn save_world_on_exit(world: Res<Arc<RwLock<world::World>>>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.iter().next().is_some() {
+        world.read().unwrap().save();
+    }
+}
+
 fn main() {
     App::new()
         .insert_resource(ClearColor(Color::BLACK))
@@ -130,13 +196,34 @@ fn main() {
             cursor_locked: true,
             ..Default::default()
         })
+        .insert_resource(ViewDistance::default())
+        .insert_resource(ChunkBudget::default())
+        .insert_resource(TargetedBlock::default())
+        .insert_resource(PlayerInventory::default())
+        .insert_resource(WorldClock::default())
         .add_startup_system(setup)
+        .add_startup_system(spawn_block_outline)
+        .add_startup_system(spawn_sky_light)
         .add_system(create_material)
+        .add_system(stream_chunks_around_player)
+        .add_system(unload_far_chunks)
         .add_system(draw_chunks_to_draw)
+        .add_system(draw_conveyor_items)
+        .add_system(draw_pipe_items)
         .add_system(update_chunks_to_update)
         .add_system(force_update_all_chunks)
         .add_system(handle_keyboard)
         .add_system(handle_mouse_motion)
+        .add_system(update_targeted_block)
+        .add_system(handle_hotbar_selection)
+        .add_system(give_creative_kit)
+        .add_system(toggle_inventory.after(handle_keyboard))
+        .add_system(draw_inventory)
+        .add_system(handle_mouse_buttons)
+        .add_system(advance_world_clock)
+        .add_system(update_sky)
+        .add_system_set(SystemSet::new().with_run_criteria(FixedTimestep::step(1.0 / TICKS_PER_SECOND)).with_system(tick_world))
+        .add_system_to_stage(CoreStage::Last, save_world_on_exit)
         .add_plugins(DefaultPlugins)
         .add_plugin(MaterialPlugin::<GameMaterial>::default())
         .add_plugin(WorldInspectorPlugin::new())
@@ -0,0 +1,296 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    block_entities::BlockEntity,
+    chunk::Chunk,
+    conveyors::{draw_items, DrawnItems},
+    inventory::ItemStack,
+    items::{offset, opposite_face, FACES},
+    positions::{ChunkPosition, WorldPosition},
+    world::{self, World},
+};
+
+const PIPE_STEPS: u32 = 10; // ticks for an item to cross a pipe
+const PIPE_CAPACITY: usize = 4; // items in a pipe at once
+const EXTRACTION_TICKS: u32 = 20; // ticks between two pulls of an extraction pipe
+const EXTRACTION_COUNT: u32 = 8; // most items pulled at once
+const MAX_ROUTE_SEARCH: usize = 1024; // pipes visited looking for a destination
+
+#[derive(Clone, Debug)]
+pub struct PipeItem {
+    pub stack: ItemStack,
+    pub route: Vec<WorldPosition>,     // the pipes left to cross then the destination, empty until one is found
+    pub progress: u32,                 // 0 when the item enters the pipe, PIPE_STEPS when it can leave
+    pub source: Option<WorldPosition>, // the block entity it came from, never a destination so items do not go back and forth
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ItemPipe {
+    pub items: Vec<PipeItem>,
+    pub filters: [Option<u16>; 6], // by index in FACES, only that item crosses the side
+    pub extracts: bool,            // pulls from the block entities around it
+    pub cooldown: u32,             // ticks before the next pull
+}
+
+impl ItemPipe {
+    pub fn allows(&self, face: usize, id: u16) -> bool {
+        self.filters[face].map_or(true, |filter| filter == id)
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.items.len() < PIPE_CAPACITY
+    }
+
+    // items coming from a belt are routed once they crossed the pipe
+    pub fn insert(&mut self, stack: ItemStack, source: Option<WorldPosition>) -> Option<ItemStack> {
+        if !self.has_room() {
+            return Some(stack);
+        }
+        self.items.push(PipeItem {
+            stack,
+            route: Vec::new(),
+            progress: 0,
+            source,
+        });
+        None
+    }
+
+    // true when move_items has something to do with the pipe
+    pub fn is_ready(&self) -> bool {
+        self.items.iter().any(|item| item.progress >= PIPE_STEPS) || (self.extracts && self.cooldown == 0 && self.has_room())
+    }
+
+    pub fn tick(&mut self) -> bool {
+        let mut changed = false;
+        for item in self.items.iter_mut().filter(|item| item.progress < PIPE_STEPS) {
+            item.progress += 1;
+            changed = true;
+        }
+        if self.extracts && self.cooldown > 0 {
+            self.cooldown -= 1;
+            changed = true;
+        }
+        changed
+    }
+}
+
+// reads the pipe in place, pipes are looked at far more often than they change
+fn read_pipe<R>(world: &World, world_position: WorldPosition, f: impl FnOnce(&ItemPipe) -> R) -> Option<R> {
+    world
+        .read_block_entity(world_position, |block_entity| match block_entity {
+            BlockEntity::ItemPipe(pipe) => Some(f(pipe)),
+            _ => None,
+        })
+        .flatten()
+}
+
+// what find_route can do with the neighbour of a pipe
+enum Step {
+    Pipe,
+    Destination,
+    Closed,
+}
+
+fn face_towards(from: WorldPosition, to: WorldPosition) -> Option<usize> {
+    (0..FACES.len()).find(|face| offset(from, *face) == to)
+}
+
+// breadth first through the loaded pipes, to the nearest block entity that takes at least part of the stack, every pipe is visited once so loops end
+fn find_route(world: &World, start: WorldPosition, stack: &ItemStack, source: Option<WorldPosition>) -> Option<Vec<WorldPosition>> {
+    let mut parents: HashMap<WorldPosition, WorldPosition> = HashMap::new();
+    let mut visited = HashSet::new();
+    visited.insert(start);
+    let mut to_visit = VecDeque::from([start]);
+    while let Some(world_position) = to_visit.pop_front() {
+        let faces: Vec<usize> = match read_pipe(world, world_position, |pipe| (0..FACES.len()).filter(|face| pipe.allows(*face, stack.id)).collect()) {
+            Some(faces) => faces,
+            None => continue,
+        };
+        for face in faces {
+            let neighbour = offset(world_position, face);
+            if Some(neighbour) == source || visited.contains(&neighbour) {
+                continue;
+            }
+            let step = world.read_block_entity(neighbour, |block_entity| match block_entity {
+                BlockEntity::ItemPipe(next) if next.allows(opposite_face(face), stack.id) => Step::Pipe,
+                BlockEntity::ItemPipe(_) => Step::Closed,
+                _ if block_entity.can_accept(stack) => Step::Destination,
+                _ => Step::Closed,
+            });
+            match step {
+                Some(Step::Pipe) => {
+                    if visited.len() < MAX_ROUTE_SEARCH {
+                        visited.insert(neighbour);
+                        parents.insert(neighbour, world_position);
+                        to_visit.push_back(neighbour);
+                    }
+                }
+                Some(Step::Destination) => {
+                    let mut route = vec![neighbour];
+                    let mut current = world_position;
+                    while current != start {
+                        route.push(current);
+                        current = parents[&current];
+                    }
+                    route.reverse();
+                    return Some(route);
+                }
+                Some(Step::Closed) | None => {}
+            }
+        }
+    }
+    None
+}
+
+// only the next step is checked, the rest of the route is checked as the item gets there
+fn next_step_is_valid(world: &World, world_position: WorldPosition, item: &PipeItem) -> bool {
+    let next = match item.route.first() {
+        Some(next) => *next,
+        None => return false,
+    };
+    let face = match face_towards(world_position, next) {
+        Some(face) => face,
+        None => return false,
+    };
+    if !read_pipe(world, world_position, |pipe| pipe.allows(face, item.stack.id)).unwrap_or(false) {
+        return false;
+    }
+    world
+        .read_block_entity(next, |block_entity| match block_entity {
+            BlockEntity::ItemPipe(pipe) => item.route.len() > 1 && pipe.allows(opposite_face(face), item.stack.id),
+            _ => item.route.len() == 1,
+        })
+        .unwrap_or(false)
+}
+
+// returns the item if it stays in the pipe, waiting for the next pipe to make room or for a destination
+fn move_item(world: &World, world_position: WorldPosition, mut item: PipeItem) -> Option<PipeItem> {
+    // a pipe was broken, a filter changed or the destination is full since the route was found
+    if !next_step_is_valid(world, world_position, &item) {
+        item.route = find_route(world, world_position, &item.stack, item.source).unwrap_or_default();
+        if item.route.is_empty() {
+            // crosses the pipe again before the next search
+            item.progress = 0;
+            return Some(item);
+        }
+    }
+    let next = item.route[0];
+    if item.route.len() == 1 {
+        // the destination takes what it can, the rest looks for another one
+        let mut rest = Some(item.stack);
+        world.with_block_entity(next, |block_entity| rest = block_entity.insert(rest.take().unwrap()));
+        return rest.map(|stack| PipeItem {
+            stack,
+            route: Vec::new(),
+            progress: PIPE_STEPS,
+            source: item.source,
+        });
+    }
+    let mut item = Some(item);
+    world.with_block_entity(next, |block_entity| {
+        if let BlockEntity::ItemPipe(pipe) = block_entity {
+            if pipe.has_room() {
+                let mut item = item.take().unwrap();
+                item.route.remove(0);
+                item.progress = 0;
+                pipe.items.push(item);
+            }
+        }
+    });
+    item
+}
+
+// an extraction pipe pulls the first stack it can route from the block entities around it, a filter on the side lets only its item in
+fn extract_items(world: &World, world_position: WorldPosition) {
+    if !read_pipe(world, world_position, |pipe| pipe.extracts && pipe.cooldown == 0 && pipe.has_room()).unwrap_or(false) {
+        return;
+    }
+    let mut extracted = None;
+    for face in 0..FACES.len() {
+        let source = offset(world_position, face);
+        // what would come out is read before anything is taken
+        let stack = world.read_block_entity(source, |block_entity| match block_entity {
+            BlockEntity::ItemPipe(_) => None,
+            _ => block_entity.extractable(EXTRACTION_COUNT),
+        });
+        let stack = match stack.flatten() {
+            Some(stack) if read_pipe(world, world_position, |pipe| pipe.allows(face, stack.id)).unwrap_or(false) => stack,
+            _ => continue,
+        };
+        if let Some(route) = find_route(world, world_position, &stack, Some(source)) {
+            extracted = world.with_block_entity(source, |block_entity| block_entity.extract(EXTRACTION_COUNT)).flatten().map(|stack| PipeItem {
+                stack,
+                route,
+                progress: 0,
+                source: Some(source),
+            });
+            break;
+        }
+    }
+    world.with_block_entity(world_position, |block_entity| {
+        if let BlockEntity::ItemPipe(pipe) = block_entity {
+            pipe.items.extend(extracted);
+            pipe.cooldown = EXTRACTION_TICKS;
+        }
+    });
+}
+
+// called by block_entities::tick_block_entities once every chunk is released, the next pipe or the destination can be in another chunk
+pub fn move_items(world: &World, mut world_positions: Vec<WorldPosition>) {
+    world_positions.sort_by_key(|world_position| (world_position.x, world_position.y, world_position.z));
+    for world_position in world_positions {
+        extract_items(world, world_position);
+        let ready = world.with_block_entity(world_position, |block_entity| match block_entity {
+            BlockEntity::ItemPipe(pipe) => {
+                let (ready, moving) = pipe.items.drain(..).partition(|item| item.progress >= PIPE_STEPS);
+                pipe.items = moving;
+                ready
+            }
+            _ => Vec::new(),
+        });
+        let staying: Vec<PipeItem> = ready.unwrap_or_default().into_iter().filter_map(|item| move_item(world, world_position, item)).collect();
+        if !staying.is_empty() {
+            world.with_block_entity(world_position, |block_entity| {
+                if let BlockEntity::ItemPipe(pipe) = block_entity {
+                    pipe.items.extend(staying);
+                }
+            });
+        }
+    }
+}
+
+// pipes are see-through, an item crosses its pipe towards the next step of its route
+pub fn draw_pipe_items(mut commands: Commands, world: Res<Arc<RwLock<world::World>>>, mut meshes: ResMut<Assets<Mesh>>, mut drawn_items: Local<DrawnItems>) {
+    let world_read_lock = world.read().unwrap();
+    let chunks: Vec<Arc<RwLock<Chunk>>> = world_read_lock.chunks.read().unwrap().values().cloned().collect();
+    let mut items: HashMap<ChunkPosition, Vec<(u16, Vec3)>> = HashMap::new();
+    for chunk in chunks {
+        let chunk_lock = chunk.read().unwrap();
+        if !chunk_lock.drawn {
+            continue;
+        }
+        for (world_position, block_entity) in chunk_lock.block_entities.read().unwrap().iter() {
+            if let BlockEntity::ItemPipe(pipe) = block_entity {
+                // cubes are rendered one unit away from their world position, see positions::translation_to_world_position
+                let center = Vec3::new(world_position.x as f32 + 1.5, world_position.y as f32 + 1.5, world_position.z as f32 + 1.5);
+                for item in pipe.items.iter() {
+                    let direction = match item.route.first().and_then(|next| face_towards(*world_position, *next)) {
+                        Some(face) => Vec3::new(FACES[face][0] as f32, FACES[face][1] as f32, FACES[face][2] as f32),
+                        None => Vec3::ZERO,
+                    };
+                    let along = item.progress as f32 / PIPE_STEPS as f32 - 0.5;
+                    items.entry(chunk_lock.position).or_insert_with(Vec::new).push((item.stack.id, center + direction * along));
+                }
+            }
+        }
+    }
+    draw_items(&mut commands, &world_read_lock, &mut meshes, &mut drawn_items, items);
+}
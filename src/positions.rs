@@ -1,3 +1,5 @@
+use bevy::prelude::Vec3;
+
 use crate::chunk::REAL_CHUNK_SIZE;
 
 pub fn world_position_to_chunk_position(world_position: WorldPosition) -> ChunkPosition {
@@ -8,6 +10,15 @@ pub fn world_position_to_chunk_position(world_position: WorldPosition) -> ChunkP
     }
 }
 
+// chunk meshes are built in padded coordinates, so rendered cubes are offset by one from their world position
+pub fn translation_to_world_position(translation: Vec3) -> WorldPosition {
+    WorldPosition {
+        x: f32::floor(translation.x - 1.0) as i32,
+        y: f32::floor(translation.y - 1.0) as i32,
+        z: f32::floor(translation.z - 1.0) as i32,
+    }
+}
+
 pub fn world_position_to_position_in_chunk(world_position: WorldPosition) -> [u32; 3] {
     [
         ((world_position.x).rem_euclid(REAL_CHUNK_SIZE as i32)) as u32 + 1,
@@ -16,6 +27,20 @@ pub fn world_position_to_position_in_chunk(world_position: WorldPosition) -> [u3
     ]
 }
 
+// position of a world cube in the padded cubes of a chunk, the 1-voxel border included
+pub fn world_position_to_padded_position_in_chunk(world_position: WorldPosition, chunk_position: ChunkPosition) -> Option<[u32; 3]> {
+    let position = [
+        world_position.x - chunk_position.x * REAL_CHUNK_SIZE as i32 + 1,
+        world_position.y - chunk_position.y * REAL_CHUNK_SIZE as i32 + 1,
+        world_position.z - chunk_position.z * REAL_CHUNK_SIZE as i32 + 1,
+    ];
+    if position.iter().all(|axis| *axis >= 0 && *axis <= REAL_CHUNK_SIZE as i32 + 1) {
+        Some([position[0] as u32, position[1] as u32, position[2] as u32])
+    } else {
+        None
+    }
+}
+
 pub fn to_world_position(value: u32, chunk_position_axis: i32) -> i32 {
     value as i32 - 1 + chunk_position_axis * REAL_CHUNK_SIZE as i32
 }
@@ -27,7 +52,7 @@ pub struct ChunkPosition {
     pub z: i32,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)] // ordered by x then y then z
 pub struct WorldPosition {
     pub x: i32,
     pub y: i32,
@@ -0,0 +1,130 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+use crate::{
+    events::GameCamera,
+    items::block_registry,
+    positions::WorldPosition,
+    world::{self, World},
+};
+
+pub const REACH_DISTANCE: f32 = 8.0;
+const OUTLINE_SCALE: f32 = 1.002;
+
+pub struct RaycastHit {
+    pub position: WorldPosition,
+    pub face: usize, // index in items::FACES of the face the ray entered through
+    pub distance: f32,
+}
+
+#[derive(Default)]
+pub struct TargetedBlock(pub Option<RaycastHit>);
+
+#[derive(Component)]
+pub struct BlockOutline;
+
+// voxel traversal from Amanatides and Woo, origin and direction are in render space
+pub fn raycast(world: &World, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+    if direction.length_squared() == 0.0 {
+        return None;
+    }
+    let direction = direction.normalize();
+    let origin = (origin - Vec3::ONE).to_array(); // see positions::translation_to_world_position
+    let direction = direction.to_array();
+
+    let mut voxel = [origin[0].floor() as i32, origin[1].floor() as i32, origin[2].floor() as i32];
+    let mut step = [0; 3];
+    let mut t_max = [f32::INFINITY; 3];
+    let mut t_delta = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            t_max[axis] = (voxel[axis] as f32 + 1.0 - origin[axis]) / direction[axis];
+            t_delta[axis] = 1.0 / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            t_max[axis] = (voxel[axis] as f32 - origin[axis]) / direction[axis];
+            t_delta[axis] = -1.0 / direction[axis];
+        }
+    }
+
+    loop {
+        let axis = if t_max[0] < t_max[1] {
+            if t_max[0] < t_max[2] {
+                0
+            } else {
+                2
+            }
+        } else if t_max[1] < t_max[2] {
+            1
+        } else {
+            2
+        };
+        let distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        voxel[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+
+        let position = WorldPosition { x: voxel[0], y: voxel[1], z: voxel[2] };
+        if let Some(id) = world.get_block(position) {
+            if block_registry().get(id).is_solid {
+                // entering through the negative side of an axis means the hit face points to the negative side
+                let face = if step[axis] > 0 { axis } else { axis + 3 };
+                return Some(RaycastHit { position, face, distance });
+            }
+        }
+    }
+}
+
+pub fn spawn_block_outline(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let half = OUTLINE_SCALE / 2.0;
+    let corners: Vec<[f32; 3]> = (0..8)
+        .map(|i| [if i & 1 == 0 { -half } else { half }, if i & 2 == 0 { -half } else { half }, if i & 4 == 0 { -half } else { half }])
+        .collect();
+    let edges: Vec<u32> = vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 2, 1, 3, 4, 6, 5, 7, 0, 4, 1, 5, 2, 6, 3, 7];
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; corners.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; corners.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, corners);
+    mesh.set_indices(Some(Indices::U32(edges)));
+
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(StandardMaterial {
+                base_color: Color::BLACK,
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(BlockOutline);
+}
+
+pub fn update_targeted_block(
+    world: Res<Arc<RwLock<world::World>>>,
+    camera: Query<&Transform, (With<GameCamera>, Without<BlockOutline>)>,
+    mut targeted_block: ResMut<TargetedBlock>,
+    mut outline: Query<(&mut Transform, &mut Visibility), With<BlockOutline>>,
+) {
+    for transform in camera.iter() {
+        targeted_block.0 = raycast(&world.read().unwrap(), transform.translation, transform.forward(), REACH_DISTANCE);
+    }
+    for (mut transform, mut visibility) in outline.iter_mut() {
+        match &targeted_block.0 {
+            Some(hit) => {
+                visibility.is_visible = true;
+                transform.translation = Vec3::new(hit.position.x as f32, hit.position.y as f32, hit.position.z as f32) + Vec3::splat(1.5);
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+}
@@ -0,0 +1,610 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use bevy::utils::HashMap;
+
+use crate::{
+    block_entities::{BlockEntity, Chest, ElectricFurnace, FluidGenerator, Furnace, Generator, FLUID_GENERATOR_CAPACITY},
+    chunk_storage::{ChunkStorage, LightArray, CHUNK_VOLUME},
+    conveyors::{BeltItem, Conveyor},
+    fluid_transport::{FluidTank, Pump, FLUID_PIPE_CAPACITY, PUMP_CAPACITY, TANK_CAPACITY},
+    inventory::{ItemStack, Slot},
+    items::{block_registry, AIR},
+    pipes::{ItemPipe, PipeItem},
+    positions::{ChunkPosition, WorldPosition},
+    structures::Modification,
+};
+
+pub const WORLD_DIRECTORY: &str = "saves/world";
+const REGION_SIZE: i32 = 16;
+const REGION_MAGIC: &[u8; 4] = b"FTBR";
+const REGION_FORMAT_VERSION: u8 = 7; // new block entity tags need no new version, old regions cannot hold them
+const OLDEST_FORMAT_VERSION: u8 = 1; // older regions are read as they were written and saved again with the current format
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_SIZE: u64 = REGION_MAGIC.len() as u64 + 1 + CHUNKS_PER_REGION as u64 * 8;
+const FLAG_FILLED: u8 = 1;
+const BLOCK_ENTITY_CHEST: u8 = 0;
+const BLOCK_ENTITY_FURNACE: u8 = 1;
+const BLOCK_ENTITY_CONVEYOR: u8 = 2;
+const BLOCK_ENTITY_GENERATOR: u8 = 3;
+const BLOCK_ENTITY_ELECTRIC_FURNACE: u8 = 4;
+const BLOCK_ENTITY_ITEM_PIPE: u8 = 5;
+const BLOCK_ENTITY_TANK: u8 = 6;
+const BLOCK_ENTITY_FLUID_PIPE: u8 = 7;
+const BLOCK_ENTITY_PUMP: u8 = 8;
+const BLOCK_ENTITY_FLUID_GENERATOR: u8 = 9;
+
+#[derive(Clone)]
+pub struct SavedChunk {
+    pub cubes: Option<ChunkStorage>, // None when only modifications from other chunks are waiting for this chunk
+    pub modifications: Vec<Modification>,
+    pub block_entities: Vec<(WorldPosition, BlockEntity)>,
+}
+
+pub struct RegionStorage {
+    directory: PathBuf,
+    file_lock: Mutex<()>,
+    pending: RwLock<HashMap<ChunkPosition, Arc<SavedChunk>>>, // chunks handed to the save thread but not written yet
+}
+
+impl RegionStorage {
+    pub fn new(directory: &str) -> Self {
+        Self {
+            directory: PathBuf::from(directory),
+            file_lock: Mutex::new(()),
+            pending: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn region_of(chunk_position: ChunkPosition) -> (ChunkPosition, usize) {
+        let region = ChunkPosition {
+            x: chunk_position.x.div_euclid(REGION_SIZE),
+            y: chunk_position.y.div_euclid(REGION_SIZE),
+            z: chunk_position.z.div_euclid(REGION_SIZE),
+        };
+        let [lx, ly, lz] = [
+            chunk_position.x.rem_euclid(REGION_SIZE),
+            chunk_position.y.rem_euclid(REGION_SIZE),
+            chunk_position.z.rem_euclid(REGION_SIZE),
+        ];
+        (region, (lx + ly * REGION_SIZE + lz * REGION_SIZE * REGION_SIZE) as usize)
+    }
+
+    fn region_path(&self, region: ChunkPosition) -> PathBuf {
+        self.directory.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    pub fn mark_pending(&self, chunk_position: ChunkPosition, saved_chunk: Arc<SavedChunk>) {
+        self.pending.write().unwrap().insert(chunk_position, saved_chunk);
+    }
+
+    pub fn pending_chunks(&self) -> HashMap<ChunkPosition, SavedChunk> {
+        self.pending.read().unwrap().iter().map(|(pos, saved_chunk)| (*pos, (**saved_chunk).clone())).collect()
+    }
+
+    pub fn load_chunk(&self, chunk_position: ChunkPosition) -> Option<Arc<SavedChunk>> {
+        if let Some(saved_chunk) = self.pending.read().unwrap().get(&chunk_position) {
+            return Some(saved_chunk.clone());
+        }
+        let _file_lock = self.file_lock.lock().unwrap();
+        let (region, index) = Self::region_of(chunk_position);
+        let mut file = File::open(self.region_path(region)).ok()?;
+        let mut header = vec![0; HEADER_SIZE as usize];
+        file.read_exact(&mut header).ok()?;
+        let version = header[4];
+        if &header[0..4] != REGION_MAGIC || !(OLDEST_FORMAT_VERSION..=REGION_FORMAT_VERSION).contains(&version) {
+            println!("Ignoring region {} {} {} written with an unknown format", region.x, region.y, region.z);
+            return None;
+        }
+        let (offset, length) = read_table_entry(&header, index);
+        if length == 0 {
+            return None;
+        }
+        let mut data = vec![0; length as usize];
+        file.seek(SeekFrom::Start(offset as u64)).ok()?;
+        file.read_exact(&mut data).ok()?;
+        decode_chunk(&data, version).map(Arc::new)
+    }
+
+    // every chunk of a region is rewritten at once, so callers should group their chunks
+    pub fn save_chunks(&self, chunks: Vec<(ChunkPosition, Arc<SavedChunk>)>) {
+        let mut regions: HashMap<ChunkPosition, Vec<(usize, ChunkPosition, Arc<SavedChunk>)>> = HashMap::new();
+        for (chunk_position, saved_chunk) in chunks {
+            let (region, index) = Self::region_of(chunk_position);
+            regions.entry(region).or_insert_with(Vec::new).push((index, chunk_position, saved_chunk));
+        }
+
+        let _file_lock = self.file_lock.lock().unwrap();
+        if let Err(error) = fs::create_dir_all(&self.directory) {
+            println!("Cannot create world directory {}: {}", self.directory.display(), error);
+            return;
+        }
+        for (region, chunks) in regions {
+            let mut entries = self.read_region_entries(region);
+            for (index, _, saved_chunk) in chunks.iter() {
+                entries[*index] = encode_chunk(saved_chunk);
+            }
+            if let Err(error) = self.write_region(region, &entries) {
+                println!("Cannot save region {} {} {}: {}", region.x, region.y, region.z, error);
+                continue;
+            }
+            let mut pending_lock = self.pending.write().unwrap();
+            for (_, chunk_position, saved_chunk) in chunks {
+                // only forget the pending copy if nobody queued a newer one meanwhile
+                if pending_lock.get(&chunk_position).map_or(false, |pending| Arc::ptr_eq(pending, &saved_chunk)) {
+                    pending_lock.remove(&chunk_position);
+                }
+            }
+        }
+    }
+
+    fn read_region_entries(&self, region: ChunkPosition) -> Vec<Vec<u8>> {
+        let mut entries = vec![Vec::new(); CHUNKS_PER_REGION];
+        let data = match fs::read(self.region_path(region)) {
+            Ok(data) => data,
+            Err(_) => return entries,
+        };
+        if data.len() < HEADER_SIZE as usize || &data[0..4] != REGION_MAGIC || !(OLDEST_FORMAT_VERSION..=REGION_FORMAT_VERSION).contains(&data[4]) {
+            return entries;
+        }
+        let version = data[4];
+        for (index, entry) in entries.iter_mut().enumerate() {
+            let (offset, length) = read_table_entry(&data, index);
+            if length > 0 && (offset + length) as usize <= data.len() {
+                let bytes = &data[offset as usize..(offset + length) as usize];
+                // the whole region is written with the current format, so the chunks of an older one are converted
+                *entry = if version == REGION_FORMAT_VERSION {
+                    bytes.to_vec()
+                } else {
+                    decode_chunk(bytes, version).map_or_else(Vec::new, |saved_chunk| encode_chunk(&saved_chunk))
+                };
+            }
+        }
+        entries
+    }
+
+    fn write_region(&self, region: ChunkPosition, entries: &Vec<Vec<u8>>) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(HEADER_SIZE as usize);
+        data.extend_from_slice(REGION_MAGIC);
+        data.push(REGION_FORMAT_VERSION);
+        let mut offset = HEADER_SIZE as u32;
+        for entry in entries.iter() {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+            offset += entry.len() as u32;
+        }
+        for entry in entries.iter() {
+            data.extend_from_slice(entry);
+        }
+
+        // write next to the region first so a crash never leaves a truncated region behind
+        let path = self.region_path(region);
+        let temporary_path = path.with_extension("region.tmp");
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(temporary_path, path)
+    }
+}
+
+fn read_table_entry(header: &[u8], index: usize) -> (u32, u32) {
+    let start = REGION_MAGIC.len() + 1 + index * 8;
+    (
+        u32::from_le_bytes(header[start..start + 4].try_into().unwrap()),
+        u32::from_le_bytes(header[start + 4..start + 8].try_into().unwrap()),
+    )
+}
+
+// block ids are written as the chunk palette and its packed indices, light and state arrays are run-length encoded
+fn encode_chunk(saved_chunk: &SavedChunk) -> Vec<u8> {
+    let mut data = Vec::new();
+    match &saved_chunk.cubes {
+        Some(cubes) => {
+            data.push(FLAG_FILLED);
+            let mut cubes = cubes.clone();
+            cubes.compact();
+            data.extend_from_slice(&(cubes.palette().len() as u16).to_le_bytes());
+            for id in cubes.palette() {
+                data.extend_from_slice(&id.to_le_bytes());
+            }
+            data.push(cubes.bits() as u8);
+            for word in cubes.words() {
+                data.extend_from_slice(&word.to_le_bytes());
+            }
+            encode_light(&mut data, cubes.natural_light());
+            for items_light in cubes.items_light() {
+                encode_light(&mut data, items_light);
+            }
+            encode_light(&mut data, cubes.states());
+        }
+        None => data.push(0),
+    }
+    data.extend_from_slice(&(saved_chunk.modifications.len() as u32).to_le_bytes());
+    for modification in saved_chunk.modifications.iter() {
+        data.extend_from_slice(&(modification.position as u32).to_le_bytes());
+        data.extend_from_slice(&modification.id.to_le_bytes());
+        data.push(modification.state);
+        data.push(modification.force as u8);
+    }
+    data.extend_from_slice(&(saved_chunk.block_entities.len() as u32).to_le_bytes());
+    for (world_position, block_entity) in saved_chunk.block_entities.iter() {
+        for coordinate in [world_position.x, world_position.y, world_position.z] {
+            data.extend_from_slice(&coordinate.to_le_bytes());
+        }
+        encode_block_entity(&mut data, block_entity);
+    }
+    data
+}
+
+fn encode_block_entity(data: &mut Vec<u8>, block_entity: &BlockEntity) {
+    match block_entity {
+        BlockEntity::Chest(chest) => {
+            data.push(BLOCK_ENTITY_CHEST);
+            data.extend_from_slice(&(chest.slots.len() as u16).to_le_bytes());
+            for slot in chest.slots.iter() {
+                encode_slot(data, slot.as_ref());
+            }
+        }
+        BlockEntity::Furnace(furnace) => {
+            data.push(BLOCK_ENTITY_FURNACE);
+            for slot in furnace.slots.iter() {
+                encode_slot(data, slot.as_ref());
+            }
+            data.extend_from_slice(&furnace.burn_time_left.to_le_bytes());
+            data.extend_from_slice(&furnace.progress.to_le_bytes());
+        }
+        BlockEntity::Conveyor(conveyor) => {
+            data.push(BLOCK_ENTITY_CONVEYOR);
+            for lane in conveyor.lanes.iter() {
+                data.extend_from_slice(&(lane.len() as u16).to_le_bytes());
+                for belt_item in lane.iter() {
+                    encode_slot(data, Some(&belt_item.stack));
+                    data.extend_from_slice(&belt_item.progress.to_le_bytes());
+                }
+            }
+        }
+        // the energy is kept by energy::EnergyNetworks
+        BlockEntity::Generator(generator) => {
+            data.push(BLOCK_ENTITY_GENERATOR);
+            encode_slot(data, generator.slots[0].as_ref());
+            data.extend_from_slice(&generator.burn_time_left.to_le_bytes());
+        }
+        BlockEntity::ElectricFurnace(electric_furnace) => {
+            data.push(BLOCK_ENTITY_ELECTRIC_FURNACE);
+            for slot in electric_furnace.slots.iter() {
+                encode_slot(data, slot.as_ref());
+            }
+            data.extend_from_slice(&electric_furnace.progress.to_le_bytes());
+        }
+        // no filter is air, items keep their route
+        BlockEntity::ItemPipe(pipe) => {
+            data.push(BLOCK_ENTITY_ITEM_PIPE);
+            data.push(pipe.extracts as u8);
+            data.extend_from_slice(&pipe.cooldown.to_le_bytes());
+            for filter in pipe.filters.iter() {
+                data.extend_from_slice(&filter.unwrap_or(AIR).to_le_bytes());
+            }
+            data.extend_from_slice(&(pipe.items.len() as u16).to_le_bytes());
+            for item in pipe.items.iter() {
+                encode_slot(data, Some(&item.stack));
+                data.extend_from_slice(&item.progress.to_le_bytes());
+                data.extend_from_slice(&(item.route.len() as u16).to_le_bytes());
+                for world_position in item.route.iter() {
+                    encode_world_position(data, *world_position);
+                }
+                match item.source {
+                    Some(source) => {
+                        data.push(1);
+                        encode_world_position(data, source);
+                    }
+                    None => data.push(0),
+                }
+            }
+        }
+        BlockEntity::Tank(tank) => {
+            data.push(BLOCK_ENTITY_TANK);
+            encode_tank(data, tank);
+        }
+        BlockEntity::FluidPipe(tank) => {
+            data.push(BLOCK_ENTITY_FLUID_PIPE);
+            encode_tank(data, tank);
+        }
+        BlockEntity::Pump(pump) => {
+            data.push(BLOCK_ENTITY_PUMP);
+            encode_tank(data, &pump.tank);
+            data.extend_from_slice(&pump.cooldown.to_le_bytes());
+        }
+        BlockEntity::FluidGenerator(fluid_generator) => {
+            data.push(BLOCK_ENTITY_FLUID_GENERATOR);
+            encode_tank(data, &fluid_generator.tank);
+            data.extend_from_slice(&fluid_generator.burn_time_left.to_le_bytes());
+        }
+    }
+}
+
+// the capacity comes from the kind of block entity, an empty tank holds air
+fn encode_tank(data: &mut Vec<u8>, tank: &FluidTank) {
+    data.extend_from_slice(&tank.fluid.unwrap_or(AIR).to_le_bytes());
+    data.extend_from_slice(&tank.amount.to_le_bytes());
+}
+
+// an empty slot is air with a count of 0, a stack carrying a block entity is followed by it
+fn encode_slot(data: &mut Vec<u8>, slot: Option<&ItemStack>) {
+    match slot {
+        Some(stack) => {
+            data.extend_from_slice(&stack.id.to_le_bytes());
+            data.extend_from_slice(&stack.count.to_le_bytes());
+            match &stack.data {
+                Some(block_entity) => {
+                    data.push(1);
+                    encode_block_entity(data, block_entity);
+                }
+                None => data.push(0),
+            }
+        }
+        None => {
+            data.extend_from_slice(&AIR.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.push(0);
+        }
+    }
+}
+
+fn decode_block_entity(reader: &mut ByteReader, version: u8) -> Option<BlockEntity> {
+    match reader.read_u8()? {
+        BLOCK_ENTITY_CHEST => {
+            let mut slots = Vec::new();
+            for _ in 0..reader.read_u16()? {
+                slots.push(decode_slot(reader, version)?);
+            }
+            Some(BlockEntity::Chest(Chest { slots }))
+        }
+        BLOCK_ENTITY_FURNACE => Some(BlockEntity::Furnace(Furnace {
+            slots: [decode_slot(reader, version)?, decode_slot(reader, version)?, decode_slot(reader, version)?],
+            burn_time_left: reader.read_u32()?,
+            progress: reader.read_u32()?,
+        })),
+        BLOCK_ENTITY_CONVEYOR => {
+            let mut conveyor = Conveyor::default();
+            for lane in conveyor.lanes.iter_mut() {
+                for _ in 0..reader.read_u16()? {
+                    // a belt item is never empty
+                    let stack = decode_slot(reader, version)??;
+                    lane.push(BeltItem { stack, progress: reader.read_u32()? });
+                }
+            }
+            Some(BlockEntity::Conveyor(conveyor))
+        }
+        BLOCK_ENTITY_GENERATOR => Some(BlockEntity::Generator(Generator {
+            slots: [decode_slot(reader, version)?],
+            burn_time_left: reader.read_u32()?,
+        })),
+        BLOCK_ENTITY_ELECTRIC_FURNACE => Some(BlockEntity::ElectricFurnace(ElectricFurnace {
+            slots: [decode_slot(reader, version)?, decode_slot(reader, version)?],
+            progress: reader.read_u32()?,
+        })),
+        BLOCK_ENTITY_ITEM_PIPE => {
+            let mut pipe = ItemPipe {
+                extracts: reader.read_u8()? == 1,
+                cooldown: reader.read_u32()?,
+                ..ItemPipe::default()
+            };
+            for filter in pipe.filters.iter_mut() {
+                *filter = Some(reader.read_u16()?).filter(|id| *id != AIR);
+            }
+            for _ in 0..reader.read_u16()? {
+                let stack = decode_slot(reader, version)??;
+                let progress = reader.read_u32()?;
+                let mut route = Vec::new();
+                for _ in 0..reader.read_u16()? {
+                    route.push(decode_world_position(reader)?);
+                }
+                let source = match reader.read_u8()? {
+                    0 => None,
+                    _ => Some(decode_world_position(reader)?),
+                };
+                pipe.items.push(PipeItem { stack, route, progress, source });
+            }
+            Some(BlockEntity::ItemPipe(pipe))
+        }
+        BLOCK_ENTITY_TANK => Some(BlockEntity::Tank(decode_tank(reader, TANK_CAPACITY)?)),
+        BLOCK_ENTITY_FLUID_PIPE => Some(BlockEntity::FluidPipe(decode_tank(reader, FLUID_PIPE_CAPACITY)?)),
+        BLOCK_ENTITY_PUMP => Some(BlockEntity::Pump(Pump {
+            tank: decode_tank(reader, PUMP_CAPACITY)?,
+            cooldown: reader.read_u32()?,
+        })),
+        BLOCK_ENTITY_FLUID_GENERATOR => Some(BlockEntity::FluidGenerator(FluidGenerator {
+            tank: decode_tank(reader, FLUID_GENERATOR_CAPACITY)?,
+            burn_time_left: reader.read_u32()?,
+        })),
+        _ => None,
+    }
+}
+
+fn encode_world_position(data: &mut Vec<u8>, world_position: WorldPosition) {
+    for coordinate in [world_position.x, world_position.y, world_position.z] {
+        data.extend_from_slice(&coordinate.to_le_bytes());
+    }
+}
+
+fn decode_world_position(reader: &mut ByteReader) -> Option<WorldPosition> {
+    Some(WorldPosition {
+        x: reader.read_i32()?,
+        y: reader.read_i32()?,
+        z: reader.read_i32()?,
+    })
+}
+
+fn decode_tank(reader: &mut ByteReader, capacity: u32) -> Option<FluidTank> {
+    let mut tank = FluidTank::new(capacity);
+    let fluid = reader.read_u16()?;
+    let amount = reader.read_u32()?;
+    if fluid != AIR {
+        tank.fill(fluid, amount);
+    }
+    Some(tank)
+}
+
+// Some(None) for an empty slot, None if the data is broken
+// slots got their block entity flag in version 7
+fn decode_slot(reader: &mut ByteReader, version: u8) -> Option<Slot> {
+    let id = reader.read_u16()?;
+    let count = reader.read_u32()?;
+    let data = match if version >= 7 { reader.read_u8()? } else { 0 } {
+        0 => None,
+        _ => Some(Box::new(decode_block_entity(reader, version)?)),
+    };
+    if id as usize >= block_registry().len() {
+        return None;
+    }
+    Some(if count == 0 { None } else { Some(ItemStack { id, count, data }) })
+}
+
+fn encode_light(data: &mut Vec<u8>, light: &LightArray) {
+    let mut runs: Vec<(u16, u8)> = Vec::new();
+    for i in 0..CHUNK_VOLUME {
+        let value = light.get(i);
+        match runs.last_mut() {
+            Some((count, last)) if *last == value && *count < u16::MAX => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    data.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (count, value) in runs {
+        data.extend_from_slice(&count.to_le_bytes());
+        data.push(value);
+    }
+}
+
+fn decode_light(reader: &mut ByteReader) -> Option<LightArray> {
+    let mut values = Vec::with_capacity(CHUNK_VOLUME);
+    for _ in 0..reader.read_u32()? {
+        let count = reader.read_u16()?;
+        let value = reader.read_u8()?;
+        values.extend(std::iter::repeat(value).take(count as usize));
+    }
+    if values.len() != CHUNK_VOLUME {
+        return None;
+    }
+    Some(LightArray::from_values(values))
+}
+
+// version 1 wrote runs of whole cubes
+fn decode_cube_runs(reader: &mut ByteReader) -> Option<ChunkStorage> {
+    let mut cubes = ChunkStorage::new();
+    let mut i = 0;
+    for _ in 0..reader.read_u32()? {
+        let count = reader.read_u16()? as usize;
+        let id = reader.read_u16()?;
+        let natural_light_level = reader.read_u8()?;
+        let items_light_level = reader.read_u8()?;
+        if i + count > CHUNK_VOLUME || id as usize >= block_registry().len() {
+            return None;
+        }
+        for i in i..i + count {
+            cubes.set_id(i, id);
+            cubes.set_natural_light(i, natural_light_level);
+            cubes.set_items_light(i, [items_light_level; 3]);
+        }
+        i += count;
+    }
+    if i != CHUNK_VOLUME {
+        return None;
+    }
+    Some(cubes)
+}
+
+// every version from OLDEST_FORMAT_VERSION is read, what an older one did not write is left at its default
+fn decode_chunk(data: &[u8], version: u8) -> Option<SavedChunk> {
+    let mut reader = ByteReader::new(data);
+    let flags = reader.read_u8()?;
+    let cubes = if flags & FLAG_FILLED != 0 && version == 1 {
+        Some(decode_cube_runs(&mut reader)?)
+    } else if flags & FLAG_FILLED != 0 {
+        let mut palette = Vec::new();
+        for _ in 0..reader.read_u16()? {
+            palette.push(reader.read_u16()?);
+        }
+        let bits = reader.read_u8()? as u32;
+        if bits > 16 {
+            return None;
+        }
+        let nb_words = if bits == 0 { 0 } else { (CHUNK_VOLUME + (64 / bits) as usize - 1) / (64 / bits) as usize };
+        let mut words = Vec::with_capacity(nb_words);
+        for _ in 0..nb_words {
+            words.push(reader.read_u64()?);
+        }
+        let natural_light = decode_light(&mut reader)?;
+        // version 2 had a single white block light, the fluid levels of version 4 are the states of fluids
+        let items_light = if version >= 3 {
+            [decode_light(&mut reader)?, decode_light(&mut reader)?, decode_light(&mut reader)?]
+        } else {
+            let items_light = decode_light(&mut reader)?;
+            [items_light.clone(), items_light.clone(), items_light]
+        };
+        let states = if version >= 4 { decode_light(&mut reader)? } else { LightArray::new(0) };
+        Some(ChunkStorage::from_raw_parts(palette, bits, words, natural_light, items_light, states)?)
+    } else {
+        None
+    };
+    let mut modifications = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        modifications.push(Modification {
+            position: reader.read_u32()? as usize,
+            id: reader.read_u16()?,
+            state: if version >= 5 { reader.read_u8()? } else { 0 },
+            force: reader.read_u8()? != 0,
+        });
+    }
+    let mut block_entities = Vec::new();
+    for _ in 0..if version >= 6 { reader.read_u32()? } else { 0 } {
+        let world_position = WorldPosition {
+            x: reader.read_i32()?,
+            y: reader.read_i32()?,
+            z: reader.read_i32()?,
+        };
+        block_entities.push((world_position, decode_block_entity(&mut reader, version)?));
+    }
+    Some(SavedChunk { cubes, modifications, block_entities })
+}
+
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + length)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.read_bytes(2)?.try_into().ok()?))
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read_bytes(4)?.try_into().ok()?))
+    }
+
+    pub fn read_i32(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.read_bytes(4)?.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.read_bytes(8)?.try_into().ok()?))
+    }
+}
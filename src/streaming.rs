@@ -0,0 +1,132 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::prelude::*;
+
+use crate::{
+    events::GameCamera,
+    positions::{translation_to_world_position, world_position_to_chunk_position, ChunkPosition},
+    world,
+};
+
+pub const VIEW_DISTANCE_HORIZONTAL: i32 = 8;
+pub const VIEW_DISTANCE_VERTICAL: i32 = 4;
+pub const UNLOAD_DISTANCE_HORIZONTAL: i32 = VIEW_DISTANCE_HORIZONTAL + 2;
+pub const UNLOAD_DISTANCE_VERTICAL: i32 = VIEW_DISTANCE_VERTICAL + 2;
+pub const MAX_RESIDENT_CHUNKS: usize = 4096;
+
+#[derive(Clone, Copy)]
+pub struct ViewDistance {
+    pub horizontal: i32,
+    pub vertical: i32,
+}
+
+impl Default for ViewDistance {
+    fn default() -> Self {
+        Self {
+            horizontal: VIEW_DISTANCE_HORIZONTAL,
+            vertical: VIEW_DISTANCE_VERTICAL,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ChunkBudget {
+    pub unload_distance: ViewDistance,
+    pub max_resident_chunks: usize,
+}
+
+impl Default for ChunkBudget {
+    fn default() -> Self {
+        Self {
+            unload_distance: ViewDistance {
+                horizontal: UNLOAD_DISTANCE_HORIZONTAL,
+                vertical: UNLOAD_DISTANCE_VERTICAL,
+            },
+            max_resident_chunks: MAX_RESIDENT_CHUNKS,
+        }
+    }
+}
+
+impl ViewDistance {
+    pub fn contains(&self, center: ChunkPosition, position: ChunkPosition) -> bool {
+        let [dx, dy, dz] = [position.x - center.x, position.y - center.y, position.z - center.z];
+        dx * dx + dz * dz <= self.horizontal * self.horizontal && dy.abs() <= self.vertical
+    }
+
+    pub fn distance_squared(&self, center: ChunkPosition, position: ChunkPosition) -> i32 {
+        let [dx, dy, dz] = [position.x - center.x, position.y - center.y, position.z - center.z];
+        dx * dx + dy * dy + dz * dz
+    }
+
+    // how far the position is outside of the cylinder, positive exactly when contains is false, a chunk above or below counts like a step out of the radius
+    pub fn distance_outside(&self, center: ChunkPosition, position: ChunkPosition) -> i32 {
+        let [dx, dy, dz] = [position.x - center.x, position.y - center.y, position.z - center.z];
+        (dx * dx + dz * dz - self.horizontal * self.horizontal).max((dy.abs() - self.vertical) * (2 * self.horizontal + 1))
+    }
+}
+
+pub fn stream_chunks_around_player(world: Res<Arc<RwLock<world::World>>>, view_distance: Res<ViewDistance>, camera: Query<&Transform, With<GameCamera>>) {
+    for transform in camera.iter() {
+        let center = world_position_to_chunk_position(translation_to_world_position(transform.translation));
+        let world_read_lock = world.read().unwrap();
+        let last_center = *world_read_lock.player_chunk_position.read().unwrap();
+        if last_center != Some(center) || view_distance.is_changed() {
+            world_read_lock.load_chunks_around(world.clone(), center, *view_distance);
+        }
+    }
+}
+
+// chunks only leave the unload distance when the player changes chunk and only go over the budget when some are loaded, otherwise there is nothing to do
+pub fn unload_far_chunks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    world: Res<Arc<RwLock<world::World>>>,
+    view_distance: Res<ViewDistance>,
+    budget: Res<ChunkBudget>,
+    mut last_pass: Local<Option<(ChunkPosition, usize)>>, // center and resident chunks after the last pass, None to check again
+) {
+    let world_read_lock = world.read().unwrap();
+    let center = match *world_read_lock.player_chunk_position.read().unwrap() {
+        Some(center) => center,
+        None => return,
+    };
+    let nb_resident = world_read_lock.chunks.read().unwrap().len();
+    if let Some((last_center, last_nb_resident)) = *last_pass {
+        if last_center == center && nb_resident <= last_nb_resident && !view_distance.is_changed() && !budget.is_changed() {
+            return;
+        }
+    }
+
+    // every chunk is checked, no order of the chunks gives a point where the rest is in range
+    let resident: Vec<ChunkPosition> = world_read_lock.chunks.read().unwrap().keys().copied().collect();
+    let mut nb_resident = resident.len();
+    let mut busy = false;
+    let mut in_range = Vec::new();
+    for pos in resident {
+        if budget.unload_distance.contains(center, pos) {
+            in_range.push(pos);
+        } else if world_read_lock.unload_chunk(pos, &mut commands, &mut meshes) {
+            nb_resident -= 1;
+        } else {
+            busy = true;
+        }
+    }
+
+    if nb_resident > budget.max_resident_chunks {
+        let mut over_budget: Vec<ChunkPosition> = in_range.into_iter().filter(|pos| !view_distance.contains(center, *pos)).collect();
+        // farthest from the view distance first
+        over_budget.sort_by_key(|pos| -view_distance.distance_outside(center, *pos));
+        for pos in over_budget {
+            if nb_resident <= budget.max_resident_chunks {
+                break;
+            }
+            if world_read_lock.unload_chunk(pos, &mut commands, &mut meshes) {
+                nb_resident -= 1;
+            } else {
+                busy = true;
+            }
+        }
+    }
+    // busy chunks are tried again next frame
+    *last_pass = if busy { None } else { Some((center, nb_resident)) };
+}
@@ -2,12 +2,14 @@ use block_mesh::ndshape::ConstShape;
 
 use crate::{
     chunk::{Chunk, ChunkShape},
-    items::Items,
+    chunk_filling::GenerationIds,
     positions::{WorldPosition, world_position_to_chunk_position, world_position_to_position_in_chunk},
 };
 
+#[derive(Clone, Copy)]
 pub struct Modification {
     pub id: u16,
+    pub state: u8,
     pub force: bool,
     pub position: usize,
 }
@@ -18,11 +20,12 @@ pub fn add_modification(modification: Modification, world_position: WorldPositio
     current_chunk.add_modification_no_update(modification, chunk_position);
 }
 
-pub fn generate_tree(world_position: WorldPosition, current_chunk: &Chunk) {
+pub fn generate_tree(world_position: WorldPosition, current_chunk: &Chunk, ids: &GenerationIds) {
     for i in 0..5 {
         add_modification(
             Modification {
-                id: Items::Wood as u16,
+                id: ids.wood,
+                state: 0, // upright
                 force: true,
                 position: ChunkShape::linearize(world_position_to_position_in_chunk(WorldPosition {
                     x: world_position.x,
@@ -43,7 +46,8 @@ pub fn generate_tree(world_position: WorldPosition, current_chunk: &Chunk) {
             for k in -1..3 {
                 add_modification(
                     Modification {
-                        id: Items::Leave as u16,
+                        id: ids.leaves,
+                        state: 0,
                         force: false,
                         position: ChunkShape::linearize(world_position_to_position_in_chunk(WorldPosition {
                             x: world_position.x + i,
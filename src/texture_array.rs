@@ -0,0 +1,199 @@
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+pub const BLOCK_TEXTURES_DIRECTORY: &str = "Textures/Blocks";
+const TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+pub fn block_texture_path(name: &str) -> String {
+    format!("{}/{}.png", BLOCK_TEXTURES_DIRECTORY, name)
+}
+
+// one handle per layer, in the order of BlockRegistry::texture_names
+pub fn load_block_textures(asset_server: &AssetServer, texture_names: &[String]) -> Vec<(String, Handle<Image>)> {
+    texture_names.iter().map(|name| (name.clone(), asset_server.load(&block_texture_path(name)))).collect()
+}
+
+// None while some textures are still loading
+pub fn block_textures_load_state(asset_server: &AssetServer, textures: &[(String, Handle<Image>)]) -> Option<Result<(), Vec<String>>> {
+    let mut errors = Vec::new();
+    for (name, handle) in textures.iter() {
+        match asset_server.get_load_state(handle.clone()) {
+            LoadState::Loaded => {}
+            LoadState::Failed => errors.push(format!("{} cannot be loaded", block_texture_path(name))),
+            _ => return None,
+        }
+    }
+    if errors.is_empty() {
+        Some(Ok(()))
+    } else {
+        Some(Err(errors))
+    }
+}
+
+// stacks the textures into the layers of a 2d array texture, every texture must have the size of the first one
+pub fn build_texture_array(images: &Assets<Image>, textures: &[(String, Handle<Image>)]) -> Result<Image, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut layers = Vec::with_capacity(textures.len());
+    for (name, handle) in textures.iter() {
+        let image = match images.get(handle) {
+            Some(image) => image,
+            None => {
+                errors.push(format!("{} is not loaded", block_texture_path(name)));
+                continue;
+            }
+        };
+        let converted;
+        let image = if image.texture_descriptor.format == TEXTURE_FORMAT {
+            image
+        } else {
+            match image.convert(TEXTURE_FORMAT) {
+                Some(image) => {
+                    converted = image;
+                    &converted
+                }
+                None => {
+                    errors.push(format!("{} has the unsupported format {:?}", block_texture_path(name), image.texture_descriptor.format));
+                    continue;
+                }
+            }
+        };
+        layers.push((name, image.texture_descriptor.size, image.data.clone()));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let size = layers[0].1;
+    let mut data = Vec::with_capacity(layers.iter().map(|(_, _, data)| data.len()).sum());
+    for (name, layer_size, layer_data) in layers.iter() {
+        if layer_size.width != size.width || layer_size.height != size.height {
+            errors.push(format!(
+                "{} is {}x{} but block textures are {}x{}",
+                block_texture_path(name),
+                layer_size.width,
+                layer_size.height,
+                size.width,
+                size.height
+            ));
+            continue;
+        }
+        data.extend_from_slice(layer_data);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Image::new(
+        Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: layers.len() as u32,
+        },
+        TextureDimension::D2,
+        data,
+        TEXTURE_FORMAT,
+    ))
+}
+
+// fragment.wgsl discards texels whose alpha is under 0.5
+const ALPHA_CUTOFF: f32 = 0.5;
+
+// appends a mip chain to every layer of a texture array, wgpu expects the mips of a layer right after it
+pub fn generate_mipmaps(image: &mut Image) {
+    let size = image.texture_descriptor.size;
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mip_level_count = 32 - size.width.max(size.height).leading_zeros();
+    let layer_length = width * height * 4;
+
+    let mut data = Vec::with_capacity(image.data.len() * 4 / 3 + layer_length);
+    for layer in image.data.chunks(layer_length) {
+        data.extend_from_slice(layer);
+        let mut texels: Vec<[f32; 4]> = layer
+            .chunks(4)
+            .map(|texel| [srgb_to_linear(texel[0]), srgb_to_linear(texel[1]), srgb_to_linear(texel[2]), texel[3] as f32 / 255.0])
+            .collect();
+        // cutout textures like leaves keep the share of visible texels they have at full size, else they fade away with distance
+        let is_cutout = texels.iter().any(|texel| texel[3] < 1.0);
+        let coverage = alpha_coverage(&texels, 1.0);
+        let (mut level_width, mut level_height) = (width, height);
+        for _ in 1..mip_level_count {
+            let next_width = (level_width / 2).max(1);
+            let next_height = (level_height / 2).max(1);
+            texels = downsample(&texels, level_width, level_height, next_width, next_height);
+            level_width = next_width;
+            level_height = next_height;
+
+            let alpha_scale = if is_cutout { coverage_alpha_scale(&texels, coverage) } else { 1.0 };
+            for texel in texels.iter() {
+                data.extend_from_slice(&[
+                    linear_to_srgb(texel[0]),
+                    linear_to_srgb(texel[1]),
+                    linear_to_srgb(texel[2]),
+                    ((texel[3] * alpha_scale).min(1.0) * 255.0).round() as u8,
+                ]);
+            }
+        }
+    }
+    image.data = data;
+    image.texture_descriptor.mip_level_count = mip_level_count;
+}
+
+// box filter where colours are weighted by alpha so transparent texels do not darken the edges of cutout textures
+fn downsample(texels: &[[f32; 4]], width: usize, height: usize, next_width: usize, next_height: usize) -> Vec<[f32; 4]> {
+    let mut next_texels = Vec::with_capacity(next_width * next_height);
+    for y in 0..next_height {
+        for x in 0..next_width {
+            let mut color: [f32; 3] = [0.0; 3];
+            let mut plain_color: [f32; 3] = [0.0; 3];
+            let mut alpha = 0.0;
+            let mut count = 0.0;
+            for sy in (y * 2)..(y * 2 + 2).min(height) {
+                for sx in (x * 2)..(x * 2 + 2).min(width) {
+                    let [r, g, b, a] = texels[sx + sy * width];
+                    color = [color[0] + r * a, color[1] + g * a, color[2] + b * a];
+                    plain_color = [plain_color[0] + r, plain_color[1] + g, plain_color[2] + b];
+                    alpha += a;
+                    count += 1.0;
+                }
+            }
+            let color = if alpha > 0.0 { color.map(|c| c / alpha) } else { plain_color.map(|c| c / count) };
+            next_texels.push([color[0], color[1], color[2], alpha / count]);
+        }
+    }
+    next_texels
+}
+
+fn alpha_coverage(texels: &[[f32; 4]], alpha_scale: f32) -> f32 {
+    texels.iter().filter(|texel| texel[3] * alpha_scale >= ALPHA_CUTOFF).count() as f32 / texels.len() as f32
+}
+
+// smallest alpha scale for which the mip covers as much as the full size texture
+fn coverage_alpha_scale(texels: &[[f32; 4]], coverage: f32) -> f32 {
+    let (mut low, mut high) = (0.0, 64.0);
+    for _ in 0..16 {
+        let middle = (low + high) / 2.0;
+        if alpha_coverage(texels, middle) < coverage {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    high
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use block_mesh::ndshape::ConstShape;
+
+use crate::{
+    block_entities::tick_block_entities,
+    chunk::{ChunkShape, REAL_CHUNK_SIZE},
+    fluids::update_fluid,
+    items::{block_registry, offset, RandomTick},
+    positions::{ChunkPosition, WorldPosition},
+    world::{self, World},
+};
+
+pub const TICKS_PER_SECOND: f64 = 20.0;
+const MAX_SCHEDULED_UPDATES_PER_TICK: usize = 1024;
+const RANDOM_TICKS_PER_CHUNK: u32 = 3;
+const RANDOM_TICK_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+const ABOVE: usize = 4; // index in FACES
+
+// the world moves forward one tick at a time whatever the frame rate, every step of the simulation happens in tick_world
+pub struct TickScheduler {
+    tick: u64,
+    next_sequence: u64,
+    queue: BTreeMap<(u64, u64), WorldPosition>,    // (tick, sequence) so updates due the same tick run in the order they were scheduled
+    scheduled: HashMap<WorldPosition, (u64, u64)>, // a position waits in the queue at most once, for its earliest update
+    random: XorShift,
+}
+
+impl Default for TickScheduler {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            next_sequence: 0,
+            queue: BTreeMap::new(),
+            scheduled: HashMap::new(),
+            random: XorShift(RANDOM_TICK_SEED),
+        }
+    }
+}
+
+impl TickScheduler {
+    pub fn schedule(&mut self, world_position: WorldPosition, delay: u64) {
+        let tick = self.tick + delay.max(1);
+        if let Some(&key) = self.scheduled.get(&world_position) {
+            if key.0 <= tick {
+                return;
+            }
+            self.queue.remove(&key);
+        }
+        let key = (tick, self.next_sequence);
+        self.next_sequence += 1;
+        self.queue.insert(key, world_position);
+        self.scheduled.insert(world_position, key);
+    }
+
+    // what is over the budget stays in front of the queue for the next tick
+    fn advance(&mut self) -> Vec<WorldPosition> {
+        self.tick += 1;
+        let mut due = Vec::new();
+        while due.len() < MAX_SCHEDULED_UPDATES_PER_TICK {
+            let (key, world_position) = match self.queue.iter().next() {
+                Some((key, world_position)) if key.0 <= self.tick => (*key, *world_position),
+                _ => break,
+            };
+            self.queue.remove(&key);
+            self.scheduled.remove(&world_position);
+            due.push(world_position);
+        }
+        due
+    }
+}
+
+// xorshift64, seeded so the same world plays the same random ticks
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+pub fn tick_world(world: Res<Arc<RwLock<world::World>>>) {
+    let world_read_lock = world.read().unwrap();
+    let due = world_read_lock.tick_scheduler.write().unwrap().advance();
+    for world_position in due {
+        scheduled_update(&world_read_lock, world_position);
+    }
+    random_ticks(&world_read_lock);
+    tick_block_entities(&world_read_lock);
+    world_read_lock.energy.write().unwrap().distribute();
+}
+
+fn scheduled_update(world: &World, world_position: WorldPosition) {
+    let id = match world.get_block(world_position) {
+        Some(id) => id,
+        None => return,
+    };
+    if block_registry().is_fluid(id) {
+        update_fluid(world, world_position);
+    }
+}
+
+// a few random cubes of every chunk each tick, chunks in a fixed order so the world stays deterministic
+fn random_ticks(world: &World) {
+    let block_registry = block_registry();
+    let mut chunks: Vec<(ChunkPosition, _)> = world.chunks.read().unwrap().iter().map(|(chunk_position, chunk)| (*chunk_position, chunk.clone())).collect();
+    chunks.sort_by_key(|(chunk_position, _)| (chunk_position.x, chunk_position.y, chunk_position.z));
+
+    let mut candidates = Vec::new();
+    for (chunk_position, chunk) in chunks {
+        let randoms: Vec<u64> = {
+            let mut tick_scheduler_lock = world.tick_scheduler.write().unwrap();
+            (0..RANDOM_TICKS_PER_CHUNK).map(|_| tick_scheduler_lock.random.next()).collect()
+        };
+        let chunk_lock = chunk.read().unwrap();
+        if !*chunk_lock.filled.read().unwrap() {
+            continue;
+        }
+        let cubes_lock = chunk_lock.cubes.read().unwrap();
+        if !cubes_lock.palette().iter().any(|id| block_registry.get(*id).random_tick.is_some()) {
+            continue;
+        }
+        for random in randoms {
+            let [x, y, z] = [0, 8, 16].map(|shift| ((random >> shift) % REAL_CHUNK_SIZE as u64) as u32);
+            let id = cubes_lock.get_id(ChunkShape::linearize([x + 1, y + 1, z + 1]) as usize);
+            if let Some(random_tick) = block_registry.get(id).random_tick {
+                let world_position = WorldPosition {
+                    x: chunk_position.x * REAL_CHUNK_SIZE as i32 + x as i32,
+                    y: chunk_position.y * REAL_CHUNK_SIZE as i32 + y as i32,
+                    z: chunk_position.z * REAL_CHUNK_SIZE as i32 + z as i32,
+                };
+                candidates.push((world_position, id, random_tick, random >> 32));
+            }
+        }
+    }
+
+    // blocks are only set once every chunk lock is released
+    for (world_position, id, random_tick, random) in candidates {
+        match random_tick {
+            RandomTick::Spread { onto } => spread(world, world_position, id, onto, random),
+        }
+    }
+}
+
+// grass like blocks die into what they spread onto when covered, else they take over a nearby uncovered cube of it
+fn spread(world: &World, world_position: WorldPosition, id: u16, onto: u16, random: u64) {
+    if is_covered(world, world_position) {
+        world.set_block(world_position, onto);
+        return;
+    }
+    let [dx, dy, dz] = [0, 2, 4].map(|shift| ((random >> shift) % 3) as i32 - 1);
+    let target = WorldPosition {
+        x: world_position.x + dx,
+        y: world_position.y + dy,
+        z: world_position.z + dz,
+    };
+    if world.get_block(target) == Some(onto) && !is_covered(world, target) {
+        world.set_block(target, id);
+    }
+}
+
+fn is_covered(world: &World, world_position: WorldPosition) -> bool {
+    world.get_block(offset(world_position, ABOVE)).map_or(false, |id| !block_registry().get(id).is_transparent)
+}
@@ -1,12 +1,24 @@
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use bevy::prelude::*;
-use bevy::utils::HashMap;
+use bevy::utils::{HashMap, HashSet};
+use linked_hash_map::LinkedHashMap;
 use linked_hash_set::LinkedHashSet;
 
-use crate::chunk::Chunk;
+use block_mesh::ndshape::ConstShape;
+
+use crate::block_entities::BlockEntity;
+use crate::chunk::{Chunk, ChunkShape};
+use crate::energy::EnergyNetworks;
+use crate::items::{block_registry, offset, FACES};
+use crate::lighting::relight_cube;
 use crate::game_material::GameMaterial;
-use crate::positions::ChunkPosition;
+use crate::positions::{world_position_to_chunk_position, world_position_to_padded_position_in_chunk, world_position_to_position_in_chunk, ChunkPosition, WorldPosition};
+use crate::region::{RegionStorage, SavedChunk, WORLD_DIRECTORY};
+use crate::streaming::ViewDistance;
+use crate::structures::Modification;
+use crate::ticks::TickScheduler;
 
 const NB_THREADS: usize = 8;
 const NB_UPDATE_THREADS: usize = 4;
@@ -15,13 +27,21 @@ const NB_UPDATE_THREADS: usize = 4;
 pub struct World {
     pub chunks: Arc<RwLock<HashMap<ChunkPosition, Arc<RwLock<Chunk>>>>>,
     pub material: RwLock<Handle<GameMaterial>>,
+    pub translucent_material: RwLock<Handle<GameMaterial>>,
     pub chunks_to_draw: Arc<RwLock<LinkedHashSet<ChunkPosition>>>,
     pub chunks_to_update: Arc<RwLock<LinkedHashSet<ChunkPosition>>>,
     pub thread_pool: rayon::ThreadPool,
     pub update_thread_pool: rayon::ThreadPool,
     pub world_thread_pool: rayon::ThreadPool,
+    pub save_thread_pool: rayon::ThreadPool,
+    pub region_storage: Arc<RegionStorage>,
     pub nb_chunks_generating: Arc<RwLock<usize>>,
+    pub chunks_generating: Arc<RwLock<HashSet<ChunkPosition>>>,
+    pub player_chunk_position: Arc<RwLock<Option<ChunkPosition>>>,
+    pub unloaded_modifications: RwLock<HashMap<ChunkPosition, LinkedHashMap<usize, Modification>>>, // modifications waiting for a chunk that is not in memory
     pub natural_light_stopped_at: RwLock<HashMap<(i32, i32), i32>>, // key: (gx, gz), value: gy -> the highest y where the light_multiplier is not 0
+    pub tick_scheduler: RwLock<TickScheduler>,
+    pub energy: RwLock<EnergyNetworks>, // saved apart from the chunks, see energy
 }
 
 impl World {
@@ -33,17 +53,26 @@ impl World {
         let thread_pool = rayon::ThreadPoolBuilder::new().num_threads(NB_THREADS).build().unwrap();
         let update_thread_pool = rayon::ThreadPoolBuilder::new().num_threads(NB_UPDATE_THREADS).build().unwrap();
         let world_thread_pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let save_thread_pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
 
         Self {
             chunks,
             material,
+            translucent_material: RwLock::new(Handle::default()),
             chunks_to_draw,
             chunks_to_update,
             thread_pool,
             update_thread_pool,
             world_thread_pool,
+            save_thread_pool,
+            region_storage: Arc::new(RegionStorage::new(WORLD_DIRECTORY)),
             nb_chunks_generating: Arc::new(RwLock::new(0)),
+            chunks_generating: Arc::new(RwLock::new(HashSet::new())),
+            player_chunk_position: Arc::new(RwLock::new(None)),
+            unloaded_modifications: RwLock::new(HashMap::new()),
             natural_light_stopped_at: RwLock::new(HashMap::new()),
+            tick_scheduler: RwLock::new(TickScheduler::default()),
+            energy: RwLock::new(EnergyNetworks::load(Path::new(WORLD_DIRECTORY))),
         }
     }
 
@@ -53,7 +82,10 @@ impl World {
 
         while self.chunks_to_update.read().unwrap().len() > 0 {
             let chunk_position = self.chunks_to_update.write().unwrap().pop_front().unwrap();
-            let chunk = chunks.read().unwrap().get(&chunk_position).unwrap().clone();
+            let chunk = match chunks.read().unwrap().get(&chunk_position) {
+                Some(chunk) => chunk.clone(),
+                None => continue,
+            };
             let chunks_to_draw = Arc::clone(&self.chunks_to_draw);
 
             self.update_thread_pool.spawn(move || {
@@ -63,45 +95,306 @@ impl World {
         }
     }
 
-    pub fn start_world(&self, world: Arc<RwLock<World>>) {
-        self.world_thread_pool.spawn(move || {
-            world.read().unwrap().create_and_fill_chunks(world.clone());
-        });
+    // None if the chunk holding the cube is not generated yet
+    pub fn get_block(&self, world_position: WorldPosition) -> Option<u16> {
+        self.get_block_state(world_position).map(|(id, _)| id)
     }
 
-    // called each time player change chunk
-    pub fn create_and_fill_chunks(&self, world: Arc<RwLock<World>>) {
-        for y in (-4..2).rev() {
-            for x in -8..9 {
-                for z in -8..9 {
-                    let pos = ChunkPosition { x, y, z };
+    // id and state of the cube, see items::StateSchema
+    pub fn get_block_state(&self, world_position: WorldPosition) -> Option<(u16, u8)> {
+        let chunk = self.chunks.read().unwrap().get(&world_position_to_chunk_position(world_position))?.clone();
+        let chunk_lock = chunk.read().unwrap();
+        if !*chunk_lock.filled.read().unwrap() {
+            return None;
+        }
+        let cubes_lock = chunk_lock.cubes.read().unwrap();
+        let i = ChunkShape::linearize(world_position_to_position_in_chunk(world_position)) as usize;
+        Some((cubes_lock.get_id(i), cubes_lock.get_state(i)))
+    }
+
+    // returns false if the chunk holding the cube is not generated yet
+    pub fn set_block(&self, world_position: WorldPosition, id: u16) -> bool {
+        self.set_block_state(world_position, id, block_registry().get(id).default_state)
+    }
+
+    pub fn set_block_state(&self, world_position: WorldPosition, id: u16, state: u8) -> bool {
+        let chunk_position = world_position_to_chunk_position(world_position);
+        let chunk = match self.chunks.read().unwrap().get(&chunk_position) {
+            Some(chunk) => chunk.clone(),
+            None => return false,
+        };
+        if !*chunk.read().unwrap().filled.read().unwrap() {
+            return false;
+        }
+        let previous = self.get_block_state(world_position);
 
-                    {
-                        let mut chunks_lock = self.chunks.write().unwrap();
-                        if !chunks_lock.contains_key(&pos) {
-                            chunks_lock.insert(pos, Arc::new(RwLock::new(Chunk::new(pos, world.clone()))));
+        // the owning chunk and every neighbour whose padding mirrors this cube
+        let mut affected_chunks = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let other_chunk_position = ChunkPosition {
+                        x: chunk_position.x + x,
+                        y: chunk_position.y + y,
+                        z: chunk_position.z + z,
+                    };
+                    let position = match world_position_to_padded_position_in_chunk(world_position, other_chunk_position) {
+                        Some(position) => ChunkShape::linearize(position) as usize,
+                        None => continue,
+                    };
+                    let modification = Modification { id, state, force: true, position };
+                    let other_chunk = self.chunks.read().unwrap().get(&other_chunk_position).cloned();
+                    match other_chunk {
+                        Some(other_chunk) if *other_chunk.read().unwrap().filled.read().unwrap() => {
+                            let other_chunk_lock = other_chunk.read().unwrap();
+                            let mut cubes_lock = other_chunk_lock.cubes.write().unwrap();
+                            cubes_lock.set_id(position, id);
+                            cubes_lock.set_state(position, state);
+                            drop(cubes_lock);
+                            *other_chunk_lock.modified.write().unwrap() = true;
+                            affected_chunks.push((other_chunk_position, other_chunk.clone()));
+                        }
+                        Some(other_chunk) => {
+                            other_chunk.read().unwrap().modifications.write().unwrap().insert(position, modification);
+                        }
+                        None => {
+                            self.unloaded_modifications.write().unwrap().entry(other_chunk_position).or_insert_with(LinkedHashMap::new).insert(position, modification);
                         }
                     }
+                }
+            }
+        }
+
+        // the content of a broken container is lost
+        if previous.map(|(previous_id, _)| previous_id) != Some(id) {
+            let chunk_lock = chunk.read().unwrap();
+            let mut block_entities_lock = chunk_lock.block_entities.write().unwrap();
+            block_entities_lock.remove(&world_position);
+            if let Some(kind) = block_registry().get(id).block_entity {
+                block_entities_lock.insert(world_position, BlockEntity::new(kind));
+            }
+            drop(block_entities_lock);
+            drop(chunk_lock);
+            // the energy of the previous block is lost with it
+            let mut energy_lock = self.energy.write().unwrap();
+            energy_lock.remove(world_position);
+            if let Some(role) = block_registry().get(id).energy {
+                energy_lock.add(world_position, id, role);
+            }
+        }
+
+        // only a new block or a new emission changes the light, a fluid changing level lets it through the same way
+        let block_registry = block_registry();
+        let emission = block_registry.get(id).emission_in_state(state);
+        if previous.map_or(true, |(previous_id, previous_state)| previous_id != id || block_registry.get(previous_id).emission_in_state(previous_state) != emission) {
+            self.relight_changed_cube(world_position, id);
+        }
+        self.schedule_block_updates(world_position);
+
+        let mut chunks_to_update_lock = self.chunks_to_update.write().unwrap();
+        for (other_chunk_position, other_chunk) in affected_chunks {
+            if other_chunk.read().unwrap().drawn {
+                chunks_to_update_lock.insert_if_absent(other_chunk_position);
+            }
+        }
+        true
+    }
+
+    // a copy for display, changes go through with_block_entity
+    pub fn block_entity(&self, world_position: WorldPosition) -> Option<BlockEntity> {
+        let chunk = self.chunks.read().unwrap().get(&world_position_to_chunk_position(world_position))?.clone();
+        let chunk_lock = chunk.read().unwrap();
+        let block_entities_lock = chunk_lock.block_entities.read().unwrap();
+        block_entities_lock.get(&world_position).cloned()
+    }
+
+    // same as with_block_entity without marking the chunk modified, for checks that change nothing
+    pub fn read_block_entity<R>(&self, world_position: WorldPosition, f: impl FnOnce(&BlockEntity) -> R) -> Option<R> {
+        let chunk = self.chunks.read().unwrap().get(&world_position_to_chunk_position(world_position))?.clone();
+        let chunk_lock = chunk.read().unwrap();
+        let block_entities_lock = chunk_lock.block_entities.read().unwrap();
+        Some(f(block_entities_lock.get(&world_position)?))
+    }
+
+    // None if the cube has no block entity or its chunk is not generated yet
+    pub fn with_block_entity<R>(&self, world_position: WorldPosition, f: impl FnOnce(&mut BlockEntity) -> R) -> Option<R> {
+        let chunk = self.chunks.read().unwrap().get(&world_position_to_chunk_position(world_position))?.clone();
+        let chunk_lock = chunk.read().unwrap();
+        let result = f(chunk_lock.block_entities.write().unwrap().get_mut(&world_position)?);
+        *chunk_lock.modified.write().unwrap() = true;
+        Some(result)
+    }
+
+    fn relight_changed_cube(&self, world_position: WorldPosition, id: u16) {
+        if block_registry().get(id).light_multiplier < 1.0 {
+            let mut natural_light_lock = self.natural_light_stopped_at.write().unwrap();
+            for column in [
+                (world_position.x, world_position.z),
+                (world_position.x - 1, world_position.z),
+                (world_position.x + 1, world_position.z),
+                (world_position.x, world_position.z - 1),
+                (world_position.x, world_position.z + 1),
+            ] {
+                let height = natural_light_lock.entry(column).or_insert(world_position.y + 1);
+                if *height <= world_position.y {
+                    *height = world_position.y + 1;
+                }
+            }
+        }
+        relight_cube(self, world_position);
+    }
+
+    // the cube and its neighbours are the only ones that can start or stop flowing after a change
+    fn schedule_block_updates(&self, world_position: WorldPosition) {
+        let block_registry = block_registry();
+        let mut positions = vec![world_position];
+        positions.extend((0..FACES.len()).map(|face| offset(world_position, face)));
+        for position in positions {
+            if let Some(fluid) = self.get_block(position).and_then(|id| block_registry.get(id).fluid) {
+                self.tick_scheduler.write().unwrap().schedule(position, fluid.tick_delay);
+            }
+        }
+    }
 
-                    let chunk = Arc::clone(self.chunks.read().unwrap().get(&pos).unwrap());
-                    if chunk.read().unwrap().drawn {
-                        continue;
+    // returns false if the chunk is busy and must be unloaded later
+    pub fn unload_chunk(&self, pos: ChunkPosition, commands: &mut Commands, meshes: &mut Assets<Mesh>) -> bool {
+        if self.chunks_generating.read().unwrap().contains(&pos) {
+            return false;
+        }
+        let chunk = match self.chunks.read().unwrap().get(&pos) {
+            Some(chunk) => chunk.clone(),
+            None => return true,
+        };
+        let mut chunk_lock = match chunk.try_write() {
+            Ok(chunk_lock) => chunk_lock,
+            Err(_) => return false,
+        };
+        self.chunks.write().unwrap().remove(&pos);
+        self.chunks_to_draw.write().unwrap().remove(&pos);
+        self.chunks_to_update.write().unwrap().remove(&pos);
+        chunk_lock.unload_mesh(commands, meshes);
+
+        // pending modifications are kept so trees crossing chunk borders are still complete once the chunk is back
+        let mut pending: Vec<(ChunkPosition, Modification)> = Vec::new();
+        if *chunk_lock.filled.read().unwrap() && *chunk_lock.modified.read().unwrap() {
+            let saved_chunk = Arc::new(chunk_lock.to_saved_chunk());
+            chunk_lock.modifications.write().unwrap().clear();
+            self.region_storage.mark_pending(pos, saved_chunk.clone());
+            let region_storage = self.region_storage.clone();
+            self.save_thread_pool.spawn(move || {
+                region_storage.save_chunks(vec![(pos, saved_chunk)]);
+            });
+        }
+        while let Some((_, modification)) = chunk_lock.modifications.write().unwrap().pop_front() {
+            pending.push((pos, modification));
+        }
+        while let Some((_, (chunk_position, modification))) = chunk_lock.other_chunks_modifications.write().unwrap().pop_front() {
+            pending.push((chunk_position, modification));
+        }
+        for (chunk_position, modification) in pending {
+            let other_chunk = self.chunks.read().unwrap().get(&chunk_position).cloned();
+            match other_chunk {
+                Some(other_chunk) => {
+                    other_chunk.read().unwrap().modifications.write().unwrap().insert(modification.position, modification);
+                    if other_chunk.read().unwrap().drawn {
+                        self.chunks_to_update.write().unwrap().insert_if_absent(chunk_position);
                     }
-                    let chunks_to_draw = Arc::clone(&self.chunks_to_draw);
-                    let nb_chunks_generating = self.nb_chunks_generating.clone();
-                    *self.nb_chunks_generating.write().unwrap() += 1;
-
-                    self.thread_pool.spawn(move || {
-                        if !*chunk.read().unwrap().filled.read().unwrap() {
-                            chunk.read().unwrap().fill_chunk();
-                            chunk.read().unwrap().modify_other_chunks();
-                        }
-                        chunk.write().unwrap().update_mesh();
-                        chunks_to_draw.write().unwrap().insert_if_absent(pos);
-                        *nb_chunks_generating.write().unwrap() -= 1;
-                    });
                 }
+                None => {
+                    self.unloaded_modifications.write().unwrap().entry(chunk_position).or_insert_with(LinkedHashMap::new).insert(modification.position, modification);
+                }
+            }
+        }
+        true
+    }
+
+    // called when the game exits, writes every modified chunk and every modification waiting for a chunk
+    pub fn save(&self) {
+        let mut to_save: HashMap<ChunkPosition, SavedChunk> = self.region_storage.pending_chunks();
+        for (pos, chunk) in self.chunks.read().unwrap().iter() {
+            let chunk_lock = chunk.read().unwrap();
+            if *chunk_lock.modified.read().unwrap() || (!*chunk_lock.filled.read().unwrap() && chunk_lock.modifications.read().unwrap().len() > 0) {
+                to_save.insert(*pos, chunk_lock.to_saved_chunk());
+            }
+            for (chunk_position, modification) in chunk_lock.other_chunks_modifications.read().unwrap().values() {
+                self.unloaded_modifications.write().unwrap().entry(*chunk_position).or_insert_with(LinkedHashMap::new).insert(modification.position, *modification);
+            }
+        }
+        for (pos, modifications) in self.unloaded_modifications.write().unwrap().drain() {
+            let saved_chunk = to_save.entry(pos).or_insert_with(|| match self.region_storage.load_chunk(pos) {
+                Some(saved_chunk) => (*saved_chunk).clone(),
+                None => SavedChunk { cubes: None, modifications: Vec::new(), block_entities: Vec::new() },
+            });
+            for (_, modification) in modifications {
+                if !saved_chunk.modifications.iter().any(|saved| saved.position == modification.position) {
+                    saved_chunk.modifications.push(modification);
+                }
+            }
+        }
+        let nb_chunks = to_save.len();
+        self.region_storage.save_chunks(to_save.into_iter().map(|(pos, saved_chunk)| (pos, Arc::new(saved_chunk))).collect());
+        self.energy.read().unwrap().save(Path::new(WORLD_DIRECTORY));
+        println!("Saved {} chunks", nb_chunks);
+    }
+
+    // called each time player change chunk
+    pub fn load_chunks_around(&self, world: Arc<RwLock<World>>, center: ChunkPosition, view_distance: ViewDistance) {
+        *self.player_chunk_position.write().unwrap() = Some(center);
+        self.world_thread_pool.spawn(move || {
+            world.read().unwrap().create_and_fill_chunks(world.clone(), center, view_distance);
+        });
+    }
+
+    fn create_and_fill_chunks(&self, world: Arc<RwLock<World>>, center: ChunkPosition, view_distance: ViewDistance) {
+        let mut positions = Vec::new();
+        for y in center.y - view_distance.vertical..=center.y + view_distance.vertical {
+            for x in center.x - view_distance.horizontal..=center.x + view_distance.horizontal {
+                for z in center.z - view_distance.horizontal..=center.z + view_distance.horizontal {
+                    let pos = ChunkPosition { x, y, z };
+                    if view_distance.contains(center, pos) {
+                        positions.push(pos);
+                    }
+                }
+            }
+        }
+        // nearest first, and from top to bottom at equal distance so natural light is cast from above
+        positions.sort_by_key(|pos| (view_distance.distance_squared(center, *pos), -pos.y));
+
+        for pos in positions {
+            {
+                let mut chunks_lock = self.chunks.write().unwrap();
+                if !chunks_lock.contains_key(&pos) {
+                    chunks_lock.insert(pos, Arc::new(RwLock::new(Chunk::new(pos, world.clone()))));
+                }
+            }
+
+            let chunk = Arc::clone(self.chunks.read().unwrap().get(&pos).unwrap());
+            if chunk.read().unwrap().drawn || !self.chunks_generating.write().unwrap().insert(pos) {
+                continue;
             }
+            let chunks_to_draw = Arc::clone(&self.chunks_to_draw);
+            let nb_chunks_generating = self.nb_chunks_generating.clone();
+            let chunks_generating = self.chunks_generating.clone();
+            let player_chunk_position = self.player_chunk_position.clone();
+            *self.nb_chunks_generating.write().unwrap() += 1;
+
+            self.thread_pool.spawn(move || {
+                // the player may have moved away since this chunk was queued
+                let still_in_view = match *player_chunk_position.read().unwrap() {
+                    Some(player_chunk_position) => view_distance.contains(player_chunk_position, pos),
+                    None => true,
+                };
+                if still_in_view {
+                    if !*chunk.read().unwrap().filled.read().unwrap() {
+                        chunk.read().unwrap().fill_chunk();
+                        chunk.read().unwrap().modify_other_chunks();
+                    }
+                    chunk.write().unwrap().update_mesh();
+                    chunks_to_draw.write().unwrap().insert_if_absent(pos);
+                }
+                chunks_generating.write().unwrap().remove(&pos);
+                *nb_chunks_generating.write().unwrap() -= 1;
+            });
         }
     }
 }